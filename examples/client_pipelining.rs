@@ -0,0 +1,127 @@
+//! Measures the throughput of pipelined commands.
+//!
+//! Usage: `cargo run --example client_pipelining -- <username> <password> [<count> [<depth>]]`
+//!
+//! Logs in, selects INBOX, and sends `count` FETCH commands (default: 1000) with at most `depth`
+//! commands in flight (default: 1, i.e., no pipelining). Compare different depths to see the
+//! effect of pipelining on the round trip bound workload.
+
+use std::time::Instant;
+
+use imap_codec::{decode::Decoder, CommandCodec};
+use imap_next::{
+    client::{Client, Event, Options},
+    stream::Stream,
+};
+use imap_types::{
+    bounded_static::IntoBoundedStatic,
+    command::{Command, CommandBody},
+    core::Tag,
+    response::{Status, StatusKind, Tagged},
+};
+use tag_generator::TagGenerator;
+use tokio::net::TcpStream;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let (Some(username), Some(password)) = (args.next(), args.next()) else {
+        eprintln!("Usage: client_pipelining <username> <password> [<count> [<depth>]]");
+        return;
+    };
+    let count: usize = args.next().map_or(1000, |arg| arg.parse().unwrap());
+    let depth: usize = args.next().map_or(1, |arg| arg.parse().unwrap());
+    assert!(depth > 0, "depth must be at least 1");
+
+    let stream = TcpStream::connect("127.0.0.1:12345").await.unwrap();
+    let mut stream = Stream::insecure(stream);
+    let mut client = Client::new(Options::default());
+
+    loop {
+        match stream.next(&mut client).await.unwrap() {
+            Event::GreetingReceived { .. } => break,
+            event => println!("unexpected event: {event:?}"),
+        }
+    }
+
+    let mut tag_generator = TagGenerator::new();
+
+    // Prepare the session, this is not measured
+    let login = CommandBody::login(username, password).unwrap();
+    execute(&mut stream, &mut client, tag_generator.generate(), login).await;
+    let select = parse_command_body("SELECT INBOX");
+    execute(&mut stream, &mut client, tag_generator.generate(), select).await;
+
+    let fetch = parse_command_body("FETCH 1 (UID FLAGS)");
+    let mut in_flight = Vec::with_capacity(depth);
+    let mut enqueued = 0;
+    let mut completed = 0;
+
+    let start = Instant::now();
+
+    while completed < count {
+        // Keep up to `depth` commands in flight
+        while enqueued < count && in_flight.len() < depth {
+            let tag = tag_generator.generate();
+            client.enqueue_command(Command {
+                tag: tag.clone(),
+                body: fetch.clone(),
+            });
+            in_flight.push(tag);
+            enqueued += 1;
+        }
+
+        match stream.next(&mut client).await.unwrap() {
+            Event::StatusReceived {
+                status: Status::Tagged(Tagged { tag, body }),
+            } => {
+                if let Some(index) = in_flight.iter().position(|t| *t == tag) {
+                    assert_eq!(body.kind, StatusKind::Ok, "FETCH failed: {body:?}");
+                    in_flight.swap_remove(index);
+                    completed += 1;
+                }
+            }
+            Event::CommandRejected { status, .. } => panic!("command rejected: {status:?}"),
+            _ => {}
+        }
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{count} commands with depth {depth} took {elapsed:?} ({:.1} commands/s)",
+        count as f64 / elapsed.as_secs_f64()
+    );
+}
+
+/// Parses a command without tag, e.g., "SELECT INBOX".
+fn parse_command_body(line: &str) -> CommandBody<'static> {
+    let line = format!("X {line}\r\n");
+    let (_, command) = CommandCodec::default().decode(line.as_bytes()).unwrap();
+    command.body.into_static()
+}
+
+/// Sends a command and waits for its successful completion.
+async fn execute(
+    stream: &mut Stream,
+    client: &mut Client,
+    tag: Tag<'static>,
+    body: CommandBody<'static>,
+) {
+    client.enqueue_command(Command {
+        tag: tag.clone(),
+        body,
+    });
+
+    loop {
+        match stream.next(&mut *client).await.unwrap() {
+            Event::StatusReceived {
+                status: Status::Tagged(Tagged { tag: got_tag, body }),
+            } if got_tag == tag => {
+                assert_eq!(body.kind, StatusKind::Ok, "command failed: {body:?}");
+                break;
+            }
+            Event::CommandRejected { status, .. } => panic!("command rejected: {status:?}"),
+            _ => {}
+        }
+    }
+}
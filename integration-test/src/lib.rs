@@ -3,4 +3,5 @@ pub mod codecs;
 pub mod mock;
 pub mod runtime;
 pub mod server_tester;
+pub mod simulation;
 pub mod test_setup;
@@ -239,6 +239,21 @@ impl ServerTester {
         }
     }
 
+    pub async fn receive_error_because_non_sync_literal_too_long(&mut self, expected_bytes: &[u8]) {
+        let error = self.receive_error().await;
+        match error {
+            server::Error::NonSyncLiteralTooLong { discarded_bytes } => {
+                assert_eq!(
+                    expected_bytes.as_bstr(),
+                    discarded_bytes.declassify().as_bstr()
+                );
+            }
+            error => {
+                panic!("Server has unexpected error: {error:?}");
+            }
+        }
+    }
+
     pub async fn receive_error_because_command_too_long(&mut self, expected_bytes: &[u8]) {
         let error = self.receive_error().await;
         match error {
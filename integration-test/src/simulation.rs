@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+
+use bstr::BStr;
+use imap_next::{
+    client::{self, Client},
+    server::{self, Server},
+    Interrupt, Io, State,
+};
+use tracing::trace;
+
+/// Options for creating an instance of `Simulation`.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct SimulationOptions {
+    /// Maximum number of bytes that are passed to the other side at once.
+    ///
+    /// `None` passes all available bytes at once. Small values allow to test messages that are
+    /// received in many fragments.
+    pub chunk_size: Option<usize>,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        Self { chunk_size: None }
+    }
+}
+
+/// Runs a `Client` directly against a `Server` without any sockets.
+///
+/// The bytes written by one side are buffered and passed to the other side only when it needs
+/// more input. Everything is executed on the current thread in a deterministic order, so edge
+/// cases like fragmented messages or unexpected responses can be tested without timing issues.
+///
+/// While waiting for an event of one side, the other side is progressed as well. Its events
+/// are buffered and returned by the next call of `next_client_event` or `next_server_event`.
+pub struct Simulation {
+    chunk_size: usize,
+    client: Client,
+    server: Server,
+    client_to_server: VecDeque<u8>,
+    server_to_client: VecDeque<u8>,
+    client_events: VecDeque<Result<client::Event, client::Error>>,
+    server_events: VecDeque<Result<server::Event, server::Error>>,
+}
+
+impl Simulation {
+    pub fn new(simulation_options: SimulationOptions, client: Client, server: Server) -> Self {
+        let chunk_size = simulation_options.chunk_size.unwrap_or(usize::MAX);
+        assert!(chunk_size > 0, "Chunk size must not be 0");
+
+        Self {
+            chunk_size,
+            client,
+            server,
+            client_to_server: VecDeque::new(),
+            server_to_client: VecDeque::new(),
+            client_events: VecDeque::new(),
+            server_events: VecDeque::new(),
+        }
+    }
+
+    pub fn client(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    pub fn server(&mut self) -> &mut Server {
+        &mut self.server
+    }
+
+    /// Injects bytes as if they were sent by the client, e.g., a malformed command.
+    pub fn inject_client_output(&mut self, bytes: &[u8]) {
+        self.client_to_server.extend(bytes);
+    }
+
+    /// Injects bytes as if they were sent by the server, e.g., an unexpected response.
+    pub fn inject_server_output(&mut self, bytes: &[u8]) {
+        self.server_to_client.extend(bytes);
+    }
+
+    /// Progresses the simulation until the client emits the next event or error.
+    pub fn next_client_event(&mut self) -> Result<client::Event, client::Error> {
+        if let Some(result) = self.client_events.pop_front() {
+            return result;
+        }
+
+        loop {
+            match self.client.next() {
+                Ok(event) => return Ok(event),
+                Err(Interrupt::Error(error)) => return Err(error),
+                Err(Interrupt::Io(Io::Output(bytes))) => self.client_to_server.extend(bytes),
+                Err(Interrupt::Io(Io::NeedMoreInput)) => {
+                    if self.server_to_client.is_empty() {
+                        self.progress_server();
+                    }
+
+                    let chunk = take_chunk(&mut self.server_to_client, self.chunk_size);
+                    assert!(
+                        !chunk.is_empty(),
+                        "Client needs more input but server has nothing to send"
+                    );
+                    trace!(bytes = ?BStr::new(&chunk), "Server passes bytes to client");
+                    self.client.enqueue_input(&chunk);
+                }
+            }
+        }
+    }
+
+    /// Progresses the simulation until the server emits the next event or error.
+    pub fn next_server_event(&mut self) -> Result<server::Event, server::Error> {
+        if let Some(result) = self.server_events.pop_front() {
+            return result;
+        }
+
+        loop {
+            match self.server.next() {
+                Ok(event) => return Ok(event),
+                Err(Interrupt::Error(error)) => return Err(error),
+                Err(Interrupt::Io(Io::Output(bytes))) => self.server_to_client.extend(bytes),
+                Err(Interrupt::Io(Io::NeedMoreInput)) => {
+                    if self.client_to_server.is_empty() {
+                        self.progress_client();
+                    }
+
+                    let chunk = take_chunk(&mut self.client_to_server, self.chunk_size);
+                    assert!(
+                        !chunk.is_empty(),
+                        "Server needs more input but client has nothing to send"
+                    );
+                    trace!(bytes = ?BStr::new(&chunk), "Client passes bytes to server");
+                    self.server.enqueue_input(&chunk);
+                }
+            }
+        }
+    }
+
+    /// Progresses the client until it needs more input or emits an error.
+    fn progress_client(&mut self) {
+        loop {
+            match self.client.next() {
+                Ok(event) => self.client_events.push_back(Ok(event)),
+                Err(Interrupt::Error(error)) => {
+                    self.client_events.push_back(Err(error));
+                    break;
+                }
+                Err(Interrupt::Io(Io::Output(bytes))) => self.client_to_server.extend(bytes),
+                Err(Interrupt::Io(Io::NeedMoreInput)) => {
+                    let chunk = take_chunk(&mut self.server_to_client, self.chunk_size);
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    trace!(bytes = ?BStr::new(&chunk), "Server passes bytes to client");
+                    self.client.enqueue_input(&chunk);
+                }
+            }
+        }
+    }
+
+    /// Progresses the server until it needs more input or emits an error.
+    fn progress_server(&mut self) {
+        loop {
+            match self.server.next() {
+                Ok(event) => self.server_events.push_back(Ok(event)),
+                Err(Interrupt::Error(error)) => {
+                    self.server_events.push_back(Err(error));
+                    break;
+                }
+                Err(Interrupt::Io(Io::Output(bytes))) => self.server_to_client.extend(bytes),
+                Err(Interrupt::Io(Io::NeedMoreInput)) => {
+                    let chunk = take_chunk(&mut self.client_to_server, self.chunk_size);
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    trace!(bytes = ?BStr::new(&chunk), "Client passes bytes to server");
+                    self.server.enqueue_input(&chunk);
+                }
+            }
+        }
+    }
+}
+
+fn take_chunk(bytes: &mut VecDeque<u8>, chunk_size: usize) -> Vec<u8> {
+    let length = bytes.len().min(chunk_size);
+    bytes.drain(..length).collect()
+}
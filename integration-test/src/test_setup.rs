@@ -1,6 +1,10 @@
 use std::net::SocketAddr;
 
-use imap_next::{client, server};
+use imap_next::{
+    client::{self, Client},
+    server::{self, Server},
+};
+use imap_types::bounded_static::ToBoundedStatic;
 use tokio::net::TcpListener;
 use tracing::trace;
 use tracing_subscriber::EnvFilter;
@@ -11,6 +15,7 @@ use crate::{
     mock::Mock,
     runtime::{Runtime, RuntimeOptions},
     server_tester::ServerTester,
+    simulation::{Simulation, SimulationOptions},
 };
 
 /// Contains all parameters for creating a test setup for the server or client side
@@ -22,6 +27,7 @@ pub struct TestSetup {
     pub server_options: server::Options,
     pub client_options: client::Options,
     pub runtime_options: RuntimeOptions,
+    pub simulation_options: SimulationOptions,
     pub init_logging: bool,
 }
 
@@ -79,6 +85,21 @@ impl TestSetup {
 
         (rt, server, client)
     }
+
+    /// Create an in-memory simulation of the server side and the client side.
+    ///
+    /// The server is initialized with the given greeting.
+    pub fn setup_simulation(self, greeting: &[u8]) -> Simulation {
+        if self.init_logging {
+            init_logging();
+        }
+
+        let greeting = self.codecs.decode_greeting(greeting).to_static();
+        let server = Server::new(self.server_options, greeting);
+        let client = Client::new(self.client_options);
+
+        Simulation::new(self.simulation_options, client, server)
+    }
 }
 
 impl Default for TestSetup {
@@ -88,6 +109,7 @@ impl Default for TestSetup {
             server_options: server::Options::default(),
             client_options: client::Options::default(),
             runtime_options: RuntimeOptions::default(),
+            simulation_options: SimulationOptions::default(),
             init_logging: true,
         }
     }
@@ -217,6 +217,51 @@ fn login_with_non_sync_literal() {
     rt.run2(server.send(status), client.receive_status(status));
 }
 
+#[test]
+fn login_with_literal_plus() {
+    let (rt, mut server, mut client) = TestSetup::default().setup_client();
+
+    let greeting = b"* OK [CAPABILITY IMAP4REV1 LITERAL+] ...\r\n";
+    rt.run2(server.send(greeting), client.receive_greeting(greeting));
+
+    // Client sends non-sync literals without waiting for continuation requests
+    let login = b"A1 LOGIN {5}\r\nABCDE {5}\r\nFGHIJ\r\n";
+    let sent_login = b"A1 LOGIN {5+}\r\nABCDE {5+}\r\nFGHIJ\r\n";
+    rt.run2(client.send_command(login), server.receive(sent_login));
+
+    let status = b"A1 NO ...\r\n";
+    rt.run2(server.send(status), client.receive_status(status));
+}
+
+#[test]
+fn login_with_literal_minus() {
+    let (rt, mut server, mut client) = TestSetup::default().setup_client();
+
+    let greeting = b"* OK ...\r\n";
+    rt.run2(server.send(greeting), client.receive_greeting(greeting));
+
+    let capability = b"* CAPABILITY IMAP4REV1 LITERAL-\r\n";
+    rt.run2(server.send(capability), client.receive_data(capability));
+
+    // Only literals up to 4096 bytes are sent as non-sync literals
+    let mut login = b"A1 LOGIN {5}\r\nABCDE {4097}\r\n".to_vec();
+    login.extend(vec![b'x'; 4097]);
+    login.extend(b"\r\n");
+    let mut sent_login = b"A1 LOGIN {5+}\r\nABCDE {4097}\r\n".to_vec();
+    sent_login.extend(vec![b'x'; 4097]);
+    sent_login.extend(b"\r\n");
+
+    let continuation_request = b"+ ...\r\n";
+    rt.run2(client.send_command(&login), async {
+        server.receive(&sent_login[..29]).await;
+        server.send(continuation_request).await;
+        server.receive(&sent_login[29..]).await;
+    });
+
+    let status = b"A1 NO ...\r\n";
+    rt.run2(server.send(status), client.receive_status(status));
+}
+
 #[test]
 fn idle_accepted() {
     let (rt, mut server, mut client) = TestSetup::default().setup_client();
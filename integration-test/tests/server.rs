@@ -1,5 +1,7 @@
 use std::time::Duration;
 
+use imap_next::LineEnding;
+use imap_types::{core::Vec1, response::Capability};
 use integration_test::test_setup::TestSetup;
 
 #[test]
@@ -16,6 +18,27 @@ fn noop() {
     rt.run2(server.send_status(status), client.receive(status));
 }
 
+#[test]
+fn noop_with_bare_lf_line_endings() {
+    let mut setup = TestSetup::default();
+    setup.server_options.line_ending = LineEnding::Lf;
+
+    let (rt, mut server, mut client) = setup.setup_server();
+
+    rt.run2(
+        server.send_greeting(b"* OK ...\r\n"),
+        client.receive(b"* OK ...\n"),
+    );
+
+    let noop = b"A1 NOOP\r\n";
+    rt.run2(client.send(noop), server.receive_command(noop));
+
+    rt.run2(
+        server.send_status(b"A1 OK ...\r\n"),
+        client.receive(b"A1 OK ...\n"),
+    );
+}
+
 #[test]
 fn noop_with_large_lines() {
     let mut setup = TestSetup::default();
@@ -161,6 +184,124 @@ fn login_with_rejected_literal() {
     }
 }
 
+#[test]
+fn login_with_discarded_non_sync_literal() {
+    let mut setup = TestSetup::default();
+    setup.server_options.max_literal_size = 4;
+
+    let (rt, mut server, mut client) = setup.setup_server();
+
+    let greeting = b"* OK ...\r\n";
+    rt.run2(server.send_greeting(greeting), client.receive(greeting));
+
+    // The client sends the literals without waiting, so the server must discard them
+    let login = b"A1 LOGIN {5+}\r\nABCDE {5+}\r\nFGHIJ\r\n";
+    rt.run2(
+        client.send(login),
+        server.receive_error_because_literal_too_long(&login[..15]),
+    );
+
+    let status = b"A1 BAD ...\r\n";
+    rt.run2_and_select(client.receive(status), server.progress_internal_responses());
+
+    let noop = b"A2 NOOP\r\n";
+    rt.run2(client.send(noop), server.receive_command(noop));
+}
+
+#[test]
+fn login_with_discarded_non_sync_literal_followed_by_sync_literal() {
+    let mut setup = TestSetup::default();
+    setup.server_options.max_literal_size = 4;
+
+    let (rt, mut server, mut client) = setup.setup_server();
+
+    let greeting = b"* OK ...\r\n";
+    rt.run2(server.send_greeting(greeting), client.receive(greeting));
+
+    // The client waits for a continuation request before sending the synchronizing literal.
+    // It gets the rejection instead, so the server must not wait for the literal.
+    let login = b"A1 LOGIN {5+}\r\nABCDE {5}\r\n";
+    rt.run2(
+        client.send(login),
+        server.receive_error_because_literal_too_long(&login[..15]),
+    );
+
+    let status = b"A1 BAD ...\r\n";
+    rt.run2_and_select(client.receive(status), server.progress_internal_responses());
+
+    let noop = b"A2 NOOP\r\n";
+    rt.run2(client.send(noop), server.receive_command(noop));
+}
+
+#[test]
+fn login_with_non_sync_literal_without_literal_plus() {
+    let mut setup = TestSetup::default();
+    setup.server_options.capabilities = Some(Vec1::from(Capability::Imap4Rev1));
+
+    let (rt, mut server, mut client) = setup.setup_server();
+
+    let greeting = b"* OK [CAPABILITY IMAP4REV1] ...\r\n";
+    rt.run2(server.send_greeting(greeting), client.receive(greeting));
+
+    // Non-sync literals are rejected because LITERAL+ wasn't announced
+    let login = b"A1 LOGIN {5+}\r\nABCDE {5+}\r\nFGHIJ\r\n";
+    rt.run2(
+        client.send(login),
+        server.receive_error_because_literal_too_long(&login[..15]),
+    );
+
+    let status = b"A1 BAD ...\r\n";
+    rt.run2_and_select(client.receive(status), server.progress_internal_responses());
+}
+
+#[test]
+fn login_with_non_sync_literal_too_long_to_discard() {
+    let mut setup = TestSetup::default();
+    setup.server_options.max_literal_size = 4;
+    setup.server_options.max_discarded_literal_size = 4;
+
+    let (rt, mut server, mut client) = setup.setup_server();
+
+    let greeting = b"* OK ...\r\n";
+    rt.run2(server.send_greeting(greeting), client.receive(greeting));
+
+    let login = b"A1 LOGIN {5+}\r\nABCDE {5+}\r\nFGHIJ\r\n";
+    rt.run2(
+        client.send(&login[..15]),
+        server.receive_error_because_non_sync_literal_too_long(&login[..15]),
+    );
+
+    let status = b"A1 BAD ...\r\n";
+    rt.run2_and_select(client.receive(status), server.progress_internal_responses());
+}
+
+#[test]
+fn login_with_non_sync_literals_exceeding_discard_budget() {
+    let mut setup = TestSetup::default();
+    setup.server_options.max_literal_size = 4;
+    setup.server_options.max_discarded_literal_size = 8;
+
+    let (rt, mut server, mut client) = setup.setup_server();
+
+    let greeting = b"* OK ...\r\n";
+    rt.run2(server.send_greeting(greeting), client.receive(greeting));
+
+    // Each literal is small enough to be discarded, but not both of them
+    let login = b"A1 LOGIN {5+}\r\nABCDE {5+}\r\nFGHIJ\r\n";
+    rt.run2(
+        client.send(&login[..20]),
+        server.receive_error_because_literal_too_long(&login[..15]),
+    );
+
+    let status = b"A1 BAD ...\r\n";
+    rt.run2_and_select(client.receive(status), server.progress_internal_responses());
+
+    rt.run2(
+        client.send(&login[20..]),
+        server.receive_error_because_non_sync_literal_too_long(&login[20..27]),
+    );
+}
+
 #[test]
 fn login_with_non_sync_literal() {
     let (rt, mut server, mut client) = TestSetup::default().setup_server();
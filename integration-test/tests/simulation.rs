@@ -0,0 +1,67 @@
+use bstr::ByteSlice;
+use imap_next::{client, server};
+use imap_types::{bounded_static::ToBoundedStatic, response::Status};
+use integration_test::{codecs::Codecs, test_setup::TestSetup};
+
+#[test]
+fn login_with_literal_received_byte_by_byte() {
+    let mut setup = TestSetup::default();
+    setup.simulation_options.chunk_size = Some(1);
+    let mut simulation = setup.setup_simulation(b"* OK ...\r\n");
+
+    assert!(matches!(
+        simulation.next_server_event(),
+        Ok(server::Event::GreetingSent { .. })
+    ));
+    assert!(matches!(
+        simulation.next_client_event(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+
+    let login = Codecs::default()
+        .decode_command(b"A1 LOGIN {5}\r\nABCDE {5}\r\nFGHIJ\r\n")
+        .to_static();
+    let handle = simulation.client().enqueue_command(login.clone());
+
+    match simulation.next_server_event() {
+        Ok(server::Event::CommandReceived { command }) => assert_eq!(login, command),
+        result => panic!("Server emitted unexpected result: {result:?}"),
+    }
+    match simulation.next_client_event() {
+        Ok(client::Event::CommandSent {
+            handle: sent_handle,
+            ..
+        }) => assert_eq!(handle, sent_handle),
+        result => panic!("Client emitted unexpected result: {result:?}"),
+    }
+
+    let status = Status::ok(Some(login.tag), None, "...").unwrap();
+    simulation.server().enqueue_status(status.clone());
+
+    match simulation.next_client_event() {
+        Ok(client::Event::StatusReceived {
+            status: received_status,
+        }) => assert_eq!(status, received_status),
+        result => panic!("Client emitted unexpected result: {result:?}"),
+    }
+}
+
+#[test]
+fn injected_gibberish_instead_of_response() {
+    let mut simulation = TestSetup::default().setup_simulation(b"* OK ...\r\n");
+
+    assert!(matches!(
+        simulation.next_client_event(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+
+    let gibberish = b"I like bananas\r\n";
+    simulation.inject_server_output(gibberish);
+
+    match simulation.next_client_event() {
+        Err(client::Error::MalformedMessage { discarded_bytes }) => {
+            assert_eq!(gibberish.as_bstr(), discarded_bytes.declassify().as_bstr());
+        }
+        result => panic!("Client emitted unexpected result: {result:?}"),
+    }
+}
@@ -56,6 +56,14 @@ pub enum Bind {
         port: u16,
         /// Cryptographic objects required to accept a TLS connection.
         identity: Identity,
+        /// Restrictions on TLS protocol versions and cipher suites.
+        #[serde(default)]
+        policy: TlsPolicy,
+        /// Path to CA certificates (in PEM format) used to verify client certificates.
+        ///
+        /// When set, clients must present a certificate issued by one of these CAs (mTLS).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_ca_path: Option<String>,
     },
 }
 
@@ -95,6 +103,27 @@ pub enum Identity {
     },
 }
 
+/// Restrictions on TLS protocol versions and cipher suites.
+///
+/// Unset fields don't restrict anything, i.e., the defaults of `rustls` are used.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TlsPolicy {
+    /// Allowed TLS protocol versions, e.g., `["1.3"]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versions: Option<Vec<TlsVersion>>,
+    /// Allowed cipher suites, e.g., `["TLS13_AES_256_GCM_SHA384"]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher_suites: Option<Vec<String>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
 /// How to establish server connections?
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(tag = "encryption")]
@@ -114,6 +143,19 @@ pub enum Connect {
         /// Port.
         #[serde(default = "default_imaps_port")]
         port: u16,
+        /// Server name used for SNI and certificate verification (`host` by default).
+        ///
+        /// Useful when `host` is an IP address or differs from the name in the certificate.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        server_name: Option<String>,
+        /// Restrictions on TLS protocol versions and cipher suites.
+        #[serde(default)]
+        policy: TlsPolicy,
+        /// Path to CA certificates (in PEM format) used to verify the server certificate.
+        ///
+        /// When unset, the platform's native certificate store is used.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        root_ca_path: Option<String>,
     },
 }
 
@@ -153,7 +195,7 @@ pub enum Error {
 
 #[cfg(test)]
 mod tests {
-    use crate::config::{Bind, Config, Connect, Identity, Service};
+    use crate::config::{Bind, Config, Connect, Identity, Service, TlsPolicy, TlsVersion};
 
     #[test]
     fn test_config() {
@@ -174,6 +216,9 @@ mod tests {
                     connect: Connect::Tls {
                         host: "127.0.0.1".into(),
                         port: 993,
+                        server_name: None,
+                        policy: TlsPolicy::default(),
+                        root_ca_path: None,
                     },
                 },
                 Service {
@@ -185,10 +230,15 @@ mod tests {
                             certificate_chain_path: "localhost.pem".into(),
                             leaf_key_path: "localhost-key.pem".into(),
                         },
+                        policy: TlsPolicy::default(),
+                        client_ca_path: None,
                     },
                     connect: Connect::Tls {
                         host: "127.0.0.1".into(),
                         port: 993,
+                        server_name: None,
+                        policy: TlsPolicy::default(),
+                        root_ca_path: None,
                     },
                 },
                 Service {
@@ -211,6 +261,8 @@ mod tests {
                             certificate_chain_path: "localhost.pem".into(),
                             leaf_key_path: "localhost-key.pem".into(),
                         },
+                        policy: TlsPolicy::default(),
+                        client_ca_path: None,
                     },
                     connect: Connect::Insecure {
                         host: "127.0.0.1".into(),
@@ -224,4 +276,66 @@ mod tests {
 
         assert_eq!(expected, got);
     }
+
+    #[test]
+    fn test_config_tls_policy() {
+        let file = r#"
+            [[services]]
+            name = "mTLS to TLS"
+
+            [services.bind]
+            encryption = "Tls"
+            host = "127.0.0.1"
+            client_ca_path = "ca.pem"
+
+            [services.bind.identity]
+            type = "CertificateChainAndLeafKey"
+            certificate_chain_path = "localhost.pem"
+            leaf_key_path = "localhost-key.pem"
+
+            [services.bind.policy]
+            versions = ["1.3"]
+            cipher_suites = ["TLS13_AES_256_GCM_SHA384"]
+
+            [services.connect]
+            encryption = "Tls"
+            host = "127.0.0.1"
+            server_name = "imap.example.org"
+            root_ca_path = "roots.pem"
+            policy = { versions = ["1.2", "1.3"] }
+        "#;
+
+        let expected = Config {
+            services: vec![Service {
+                name: "mTLS to TLS".into(),
+                bind: Bind::Tls {
+                    host: "127.0.0.1".into(),
+                    port: 993,
+                    identity: Identity::CertificateChainAndLeafKey {
+                        certificate_chain_path: "localhost.pem".into(),
+                        leaf_key_path: "localhost-key.pem".into(),
+                    },
+                    policy: TlsPolicy {
+                        versions: Some(vec![TlsVersion::Tls13]),
+                        cipher_suites: Some(vec!["TLS13_AES_256_GCM_SHA384".into()]),
+                    },
+                    client_ca_path: Some("ca.pem".into()),
+                },
+                connect: Connect::Tls {
+                    host: "127.0.0.1".into(),
+                    port: 993,
+                    server_name: Some("imap.example.org".into()),
+                    policy: TlsPolicy {
+                        versions: Some(vec![TlsVersion::Tls12, TlsVersion::Tls13]),
+                        cipher_suites: None,
+                    },
+                    root_ca_path: Some("roots.pem".into()),
+                },
+            }],
+        };
+
+        let got = toml::from_str(file).unwrap();
+
+        assert_eq!(expected, got);
+    }
 }
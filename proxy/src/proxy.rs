@@ -1,4 +1,8 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock, Weak},
+    time::{Duration, SystemTime},
+};
 
 use colored::Colorize;
 use imap_next::{
@@ -12,33 +16,33 @@ use imap_types::{
     extensions::idle::IdleDone,
     response::{Code, Status},
 };
-use once_cell::sync::Lazy;
 use thiserror::Error;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::MissedTickBehavior,
+};
 use tokio_rustls::{
-    rustls::{pki_types::ServerName, ClientConfig, RootCertStore, ServerConfig},
+    rustls::{
+        pki_types::ServerName,
+        server::{VerifierBuilderError, WebPkiClientVerifier},
+        ClientConfig, RootCertStore, ServerConfig,
+    },
     TlsAcceptor, TlsConnector,
 };
 use tracing::{error, info, trace};
 
 use crate::{
     config::{Bind, Connect, Identity, Service},
-    util::{self, ControlFlow, IdentityError},
+    util::{self, ControlFlow, IdentityError, RootCertificatesError, TlsPolicyError},
 };
 
-static ROOT_CERT_STORE: Lazy<RootCertStore> = Lazy::new(|| {
-    let mut root_store = RootCertStore::empty();
-
-    for cert in rustls_native_certs::load_native_certs().unwrap() {
-        root_store.add(cert).unwrap();
-    }
-
-    root_store
-});
-
 const LITERAL_ACCEPT_TEXT: &str = "proxy: Literal accepted by proxy";
 const LITERAL_REJECT_TEXT: &str = "proxy: Literal rejected by proxy";
 const COMMAND_REJECTED_TEXT: &str = "proxy: Command rejected by server";
+const STARTTLS_REJECTED_TEXT: &str = "proxy: STARTTLS not supported by proxy";
+
+/// How often the bind-side identity files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Error)]
 pub enum ProxyError {
@@ -48,6 +52,14 @@ pub enum ProxyError {
     Identity(#[from] IdentityError),
     #[error(transparent)]
     Tls(#[from] tokio_rustls::rustls::Error),
+    #[error(transparent)]
+    TlsPolicy(#[from] TlsPolicyError),
+    #[error(transparent)]
+    RootCertificates(#[from] RootCertificatesError),
+    #[error(transparent)]
+    ClientVerifier(#[from] VerifierBuilderError),
+    #[error("Invalid server name \"{server_name}\"")]
+    InvalidServerName { server_name: String },
 }
 
 pub trait State: Send + 'static {}
@@ -59,12 +71,30 @@ pub struct Proxy<S: State> {
 
 pub struct BoundState {
     listener: TcpListener,
+    acceptor: Option<ReloadingAcceptor>,
+    connector: Option<TlsConnector>,
 }
 
 impl State for BoundState {}
 
 impl Proxy<BoundState> {
     pub async fn bind(service: Service) -> Result<Self, ProxyError> {
+        // Load the identity early so that misconfigurations are detected on startup.
+        let acceptor = match &service.bind {
+            Bind::Tls { .. } => Some(ReloadingAcceptor::new(service.bind.clone())?),
+            Bind::Insecure { .. } => None,
+        };
+
+        // Load the root certificates once and early as well. They are shared by all server
+        // connections.
+        let connector = match &service.connect {
+            Connect::Tls { .. } => {
+                let config = client_config(&service.connect)?;
+                Some(TlsConnector::from(Arc::new(config)))
+            }
+            Connect::Insecure { .. } => None,
+        };
+
         // Accept arbitrary number of connections.
         let bind_addr_port = service.bind.addr_port();
         let listener = TcpListener::bind(&bind_addr_port).await?;
@@ -72,7 +102,11 @@ impl Proxy<BoundState> {
 
         Ok(Self {
             service,
-            state: BoundState { listener },
+            state: BoundState {
+                listener,
+                acceptor,
+                connector,
+            },
         })
     }
 
@@ -80,39 +114,12 @@ impl Proxy<BoundState> {
         let (client_to_proxy, client_addr) = self.state.listener.accept().await?;
         info!(?client_addr, "Accepted client");
 
-        let client_to_proxy = match &self.service.bind {
-            Bind::Tls { identity, .. } => {
-                let config = {
-                    let (certificate_chain, leaf_key) = match identity {
-                        Identity::CertificateChainAndLeafKey {
-                            certificate_chain_path,
-                            leaf_key_path,
-                        } => {
-                            let certificate_chain =
-                                util::load_certificate_chain_pem(certificate_chain_path)?;
-                            let leaf_key = util::load_leaf_key_pem(leaf_key_path)?;
-
-                            (certificate_chain, leaf_key)
-                        }
-                    };
-
-                    let mut config = ServerConfig::builder()
-                        .with_no_client_auth()
-                        // Note: The name is misleading. We provide the full chain here.
-                        .with_single_cert(certificate_chain, leaf_key)?;
-
-                    config.alpn_protocols = vec![b"imap".to_vec()];
-
-                    config
-                };
-
-                // TODO(#146): The acceptor should really be part of the proxy initialization.
-                //             However, for testing purposes, it's nice to create it on-the-fly.
-                let acceptor = TlsAcceptor::from(Arc::new(config));
-
+        let client_to_proxy = match &self.state.acceptor {
+            Some(acceptor) => {
+                let acceptor = acceptor.acceptor();
                 Stream::tls(acceptor.accept(client_to_proxy).await?.into())
             }
-            Bind::Insecure { .. } => Stream::insecure(client_to_proxy),
+            None => Stream::insecure(client_to_proxy),
         };
 
         Ok(Proxy {
@@ -120,14 +127,204 @@ impl Proxy<BoundState> {
             state: ClientAcceptedState {
                 client_addr,
                 client_to_proxy,
+                connector: self.state.connector.clone(),
             },
         })
     }
 }
 
+/// [`TlsAcceptor`] that is rebuilt whenever one of the bind-side identity files changes.
+///
+/// This allows rotating certificates (e.g., issued by an ACME client) without a restart.
+/// Already established connections are not affected.
+struct ReloadingAcceptor {
+    current: Arc<RwLock<TlsAcceptor>>,
+}
+
+impl ReloadingAcceptor {
+    fn new(bind: Bind) -> Result<Self, ProxyError> {
+        let modified = modification_times(&bind);
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(&bind)?));
+        let current = Arc::new(RwLock::new(acceptor));
+
+        tokio::spawn(reload_acceptor(bind, modified, Arc::downgrade(&current)));
+
+        Ok(Self { current })
+    }
+
+    /// Returns the current acceptor.
+    fn acceptor(&self) -> TlsAcceptor {
+        // Unwrap: The lock is never held across a panicking operation.
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// Periodically checks the identity files and replaces the acceptor when they have changed.
+///
+/// The files are checked and loaded on the blocking thread pool so that a slow disk doesn't
+/// delay accepting clients. The task ends when the [`ReloadingAcceptor`] is dropped.
+async fn reload_acceptor(
+    bind: Bind,
+    mut modified: Vec<Option<SystemTime>>,
+    current: Weak<RwLock<TlsAcceptor>>,
+) {
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately, but the acceptor was just loaded.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        if current.strong_count() == 0 {
+            break;
+        }
+
+        let result = {
+            let bind = bind.clone();
+            let previous_modified = modified.clone();
+
+            tokio::task::spawn_blocking(move || {
+                let modified = modification_times(&bind);
+                if modified == previous_modified {
+                    return None;
+                }
+
+                Some(server_config(&bind).map(|config| (config, modified)))
+            })
+            .await
+        };
+
+        match result {
+            Ok(None) => {}
+            Ok(Some(Ok((config, new_modified)))) => {
+                let Some(current) = current.upgrade() else {
+                    break;
+                };
+
+                info!("Reloaded TLS identity");
+                // Unwrap: The lock is never held across a panicking operation.
+                *current.write().unwrap() = TlsAcceptor::from(Arc::new(config));
+                modified = new_modified;
+            }
+            Ok(Some(Err(error))) => {
+                // The files might be in the middle of being replaced. We keep the previous
+                // identity and try again on the next check.
+                error!(?error, "Failed to reload TLS identity, using previous one");
+            }
+            Err(error) => {
+                error!(?error, "Failed to reload TLS identity, using previous one");
+            }
+        }
+    }
+}
+
+fn modification_times(bind: &Bind) -> Vec<Option<SystemTime>> {
+    let Bind::Tls {
+        identity:
+            Identity::CertificateChainAndLeafKey {
+                certificate_chain_path,
+                leaf_key_path,
+            },
+        client_ca_path,
+        ..
+    } = bind
+    else {
+        return Vec::new();
+    };
+
+    [
+        Some(certificate_chain_path),
+        Some(leaf_key_path),
+        client_ca_path.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|path| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    })
+    .collect()
+}
+
+fn server_config(bind: &Bind) -> Result<ServerConfig, ProxyError> {
+    let Bind::Tls {
+        identity,
+        policy,
+        client_ca_path,
+        ..
+    } = bind
+    else {
+        unreachable!("server config requested for insecure bind");
+    };
+
+    let (certificate_chain, leaf_key) = match identity {
+        Identity::CertificateChainAndLeafKey {
+            certificate_chain_path,
+            leaf_key_path,
+        } => {
+            let certificate_chain = util::load_certificate_chain_pem(certificate_chain_path)?;
+            let leaf_key = util::load_leaf_key_pem(leaf_key_path)?;
+
+            (certificate_chain, leaf_key)
+        }
+    };
+
+    let provider = util::crypto_provider(policy)?;
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&util::protocol_versions(policy))?;
+
+    let builder = match client_ca_path {
+        Some(client_ca_path) => {
+            let mut client_roots = RootCertStore::empty();
+            for certificate in util::load_certificate_chain_pem(client_ca_path)? {
+                client_roots.add(certificate)?;
+            }
+
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(client_roots), provider)
+                    .build()?;
+
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    // Note: The name is misleading. We provide the full chain here.
+    let mut config = builder.with_single_cert(certificate_chain, leaf_key)?;
+
+    config.alpn_protocols = vec![b"imap".to_vec()];
+
+    Ok(config)
+}
+
+fn client_config(connect: &Connect) -> Result<ClientConfig, ProxyError> {
+    let Connect::Tls {
+        policy,
+        root_ca_path,
+        ..
+    } = connect
+    else {
+        unreachable!("client config requested for insecure connect");
+    };
+
+    let root_store = util::load_root_certificates(root_ca_path.as_deref())?;
+    let mut config = ClientConfig::builder_with_provider(util::crypto_provider(policy)?)
+        .with_protocol_versions(&util::protocol_versions(policy))?
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+
+    // See <https://www.iana.org/assignments/tls-extensiontype-values/tls-extensiontype-values.xhtml#alpn-protocol-ids>
+    config.alpn_protocols = vec![b"imap".to_vec()];
+
+    Ok(config)
+}
+
 pub struct ClientAcceptedState {
     client_addr: SocketAddr,
     client_to_proxy: Stream,
+    connector: Option<TlsConnector>,
 }
 
 impl State for ClientAcceptedState {}
@@ -143,23 +340,28 @@ impl Proxy<ClientAcceptedState> {
         let stream_to_server = TcpStream::connect(&server_addr_port).await?;
 
         let proxy_to_server = match self.service.connect {
-            Connect::Tls { ref host, .. } => {
-                let config = {
-                    let mut config = ClientConfig::builder()
-                        .with_root_certificates(ROOT_CERT_STORE.clone())
-                        .with_no_client_auth();
-
-                    // See <https://www.iana.org/assignments/tls-extensiontype-values/tls-extensiontype-values.xhtml#alpn-protocol-ids>
-                    config.alpn_protocols = vec![b"imap".to_vec()];
-
-                    config
-                };
-
-                let connector = TlsConnector::from(Arc::new(config));
-                let dnsname = ServerName::try_from(host.clone()).unwrap();
+            Connect::Tls {
+                ref host,
+                ref server_name,
+                ..
+            } => {
+                // Unwrap: The connector is always created for TLS in `Proxy::bind`.
+                let connector = self.state.connector.as_ref().unwrap();
+                // Note: Both DNS names and IP addresses are valid server names.
+                let name = server_name.as_ref().unwrap_or(host);
+                let server_name = ServerName::try_from(name.clone()).map_err(|_| {
+                    ProxyError::InvalidServerName {
+                        server_name: name.clone(),
+                    }
+                })?;
 
                 info!(?server_addr_port, "Starting TLS with server");
-                Stream::tls(connector.connect(dnsname, stream_to_server).await?.into())
+                Stream::tls(
+                    connector
+                        .connect(server_name, stream_to_server)
+                        .await?
+                        .into(),
+                )
             }
             Connect::Insecure { .. } => Stream::insecure(stream_to_server),
         };
@@ -222,7 +424,7 @@ impl Proxy<ConnectedState> {
         loop {
             let control_flow = tokio::select! {
                 event = client_to_proxy_stream.next(&mut client_to_proxy) => {
-                    handle_client_event(event, &mut proxy_to_server)
+                    handle_client_event(event, &mut client_to_proxy, &mut proxy_to_server)
                 }
                 event = proxy_to_server_stream.next(&mut proxy_to_server) => {
                     handle_server_event(event, &mut client_to_proxy)
@@ -238,6 +440,7 @@ impl Proxy<ConnectedState> {
 
 fn handle_client_event(
     result: Result<server::Event, stream::Error<server::Error>>,
+    client_to_proxy: &mut Server,
     proxy_to_server: &mut Client,
 ) -> ControlFlow {
     let event = match result {
@@ -271,6 +474,18 @@ fn handle_client_event(
             error!(role = "c2p", %error, ?discarded_bytes, "Discard client message");
             return ControlFlow::Continue;
         }
+        Err(stream::Error::State(
+            ref error @ server::Error::NonSyncLiteralTooLong {
+                ref discarded_bytes,
+            },
+        )) => {
+            error!(role = "c2p", %error, ?discarded_bytes, "Connection terminated");
+            return ControlFlow::Abort;
+        }
+        Err(stream::Error::State(error)) => {
+            error!(role = "c2p", %error, "Connection terminated");
+            return ControlFlow::Abort;
+        }
     };
 
     match event {
@@ -322,6 +537,34 @@ fn handle_client_event(
             let handle = proxy_to_server.set_idle_done();
             trace!(role = "p2s", ?handle, "set_idle_done");
         }
+        server::Event::CommandRateLimited { command } => {
+            // The proxy doesn't limit pending commands, the server is responsible for that
+            error!(
+                role = "c2p",
+                ?command,
+                "Unexpected command rejected by proxy"
+            );
+        }
+        server::Event::IdReceived { parameters } => {
+            // The proxy doesn't set ID parameters, so ID commands are forwarded
+            error!(role = "c2p", ?parameters, "Unexpected ID answered by proxy");
+        }
+        server::Event::StartTlsCommandReceived { tag } => {
+            trace!(role = "c2p", ?tag, "STARTTLS");
+
+            // Not expected because the proxy doesn't announce STARTTLS. Reject it anyway,
+            // clients should use TLS from the start
+            // Unwrap: This should never fail because the text is not Base64.
+            let status = Status::bad(Some(tag), None, STARTTLS_REJECTED_TEXT).unwrap();
+            // Unwrap: The server just received STARTTLS
+            let handle = client_to_proxy.starttls_reject(status).unwrap();
+            trace!(role = "p2c", ?handle, "starttls_reject");
+        }
+        server::Event::TlsHandshakeRequired { handle } => {
+            // The proxy never accepts STARTTLS
+            error!(role = "p2c", ?handle, "Unexpected TLS handshake");
+            return ControlFlow::Abort;
+        }
     }
 
     ControlFlow::Continue
@@ -356,6 +599,10 @@ fn handle_server_event(
             error!(role = "c2p", %error, ?discarded_bytes, "Discard server message");
             return ControlFlow::Continue;
         }
+        Err(stream::Error::State(error)) => {
+            error!(role = "s2p", %error, "Connection terminated");
+            return ControlFlow::Abort;
+        }
     };
 
     match event {
@@ -467,6 +714,20 @@ fn handle_server_event(
         client::Event::IdleDoneSent { handle } => {
             trace!(role = "p2c", ?handle, "--->");
         }
+        client::Event::StartTlsAborted { handle, mut status } => {
+            trace!(role = "s2p", ?handle, status=%format!("{:?}", status).blue(), "<--|");
+
+            util::filter_capabilities_in_status(&mut status);
+
+            let handle = client_to_proxy.enqueue_status(status);
+            trace!(role = "p2c", ?handle, "enqueue_status");
+        }
+        client::Event::TlsHandshakeRequired { handle, .. } => {
+            // The proxy doesn't announce STARTTLS, but forwards the command if a client sends it
+            // anyway. The connection to the server can't be upgraded.
+            error!(role = "s2p", ?handle, "Unexpected TLS handshake");
+            return ControlFlow::Abort;
+        }
     }
 
     ControlFlow::Continue
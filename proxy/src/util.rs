@@ -1,4 +1,4 @@
-use std::{fs::File, io::BufReader, path::Path};
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
 
 use imap_types::{
     auth::AuthMechanism,
@@ -9,9 +9,15 @@ use imap_types::{
     },
 };
 use thiserror::Error;
-use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::{
+    crypto::{aws_lc_rs, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    version, RootCertStore, SupportedCipherSuite, SupportedProtocolVersion, DEFAULT_VERSIONS,
+};
 use tracing::warn;
 
+use crate::config::{TlsPolicy, TlsVersion};
+
 pub enum ControlFlow {
     Continue,
     Abort,
@@ -193,3 +199,127 @@ pub fn load_leaf_key_pem<'a, P: AsRef<Path>>(path: P) -> Result<PrivateKeyDer<'a
         }),
     }
 }
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Error)]
+pub enum RootCertificatesError {
+    #[error(transparent)]
+    Identity(#[from] IdentityError),
+    #[error("No valid root certificates in \"{path}\"")]
+    Empty { path: String },
+    #[cfg(not(feature = "webpki-roots"))]
+    #[error("No root certificates available ({reason})")]
+    Unavailable { reason: String },
+}
+
+/// Load the root certificates used to verify server certificates.
+///
+/// Uses the certificates from `path` when given. Otherwise, uses the platform's native
+/// certificate store and, when it is broken or empty, falls back to the bundled Mozilla root
+/// certificates (requires the `webpki-roots` feature).
+pub fn load_root_certificates(path: Option<&str>) -> Result<RootCertStore, RootCertificatesError> {
+    let mut root_store = RootCertStore::empty();
+
+    if let Some(path) = path {
+        let certificates = load_certificate_chain_pem(path)?;
+        let (valid, invalid) = root_store.add_parsable_certificates(certificates);
+        if invalid > 0 {
+            warn!(path, invalid, "Ignored invalid root certificates");
+        }
+
+        return if valid > 0 {
+            Ok(root_store)
+        } else {
+            Err(RootCertificatesError::Empty {
+                path: path.to_owned(),
+            })
+        };
+    }
+
+    let reason = match rustls_native_certs::load_native_certs() {
+        Ok(certificates) => {
+            let (valid, invalid) = root_store.add_parsable_certificates(certificates);
+            if invalid > 0 {
+                warn!(invalid, "Ignored invalid native root certificates");
+            }
+
+            if valid > 0 {
+                return Ok(root_store);
+            }
+
+            "native certificate store is empty".to_owned()
+        }
+        Err(error) => format!("native certificate store is broken: {error}"),
+    };
+
+    fallback_root_certificates(reason)
+}
+
+#[cfg(feature = "webpki-roots")]
+fn fallback_root_certificates(reason: String) -> Result<RootCertStore, RootCertificatesError> {
+    warn!(%reason, "Falling back to bundled root certificates");
+
+    let mut root_store = RootCertStore::empty();
+    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    Ok(root_store)
+}
+
+#[cfg(not(feature = "webpki-roots"))]
+fn fallback_root_certificates(reason: String) -> Result<RootCertStore, RootCertificatesError> {
+    Err(RootCertificatesError::Unavailable { reason })
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Error)]
+pub enum TlsPolicyError {
+    #[error("Unknown cipher suite \"{name}\"")]
+    UnknownCipherSuite { name: String },
+}
+
+/// Create a crypto provider offering only the cipher suites allowed by the policy.
+pub fn crypto_provider(policy: &TlsPolicy) -> Result<Arc<CryptoProvider>, TlsPolicyError> {
+    let mut provider = aws_lc_rs::default_provider();
+
+    if let Some(allowed) = &policy.cipher_suites {
+        for name in allowed {
+            if !provider
+                .cipher_suites
+                .iter()
+                .any(|suite| cipher_suite_name(suite) == *name)
+            {
+                return Err(TlsPolicyError::UnknownCipherSuite { name: name.clone() });
+            }
+        }
+
+        provider
+            .cipher_suites
+            .retain(|suite| allowed.contains(&cipher_suite_name(suite)));
+    }
+
+    Ok(Arc::new(provider))
+}
+
+/// Return the TLS protocol versions allowed by the policy.
+///
+/// Note: `rustls` rejects the policy later if none of the allowed cipher suites is usable with
+/// the allowed versions.
+pub fn protocol_versions(policy: &TlsPolicy) -> Vec<&'static SupportedProtocolVersion> {
+    match &policy.versions {
+        Some(versions) => versions
+            .iter()
+            .map(|tls_version| match tls_version {
+                TlsVersion::Tls12 => &version::TLS12,
+                TlsVersion::Tls13 => &version::TLS13,
+            })
+            .collect(),
+        None => DEFAULT_VERSIONS.to_vec(),
+    }
+}
+
+fn cipher_suite_name(suite: &SupportedCipherSuite) -> String {
+    // The `Debug` representation is the IANA name, e.g., "TLS13_AES_256_GCM_SHA384".
+    format!("{:?}", suite.suite())
+}
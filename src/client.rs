@@ -2,23 +2,33 @@ use std::fmt::{Debug, Formatter};
 
 use imap_codec::{
     decode::{GreetingDecodeError, ResponseDecodeError},
+    encode::Fragment,
     AuthenticateDataCodec, CommandCodec, GreetingCodec, IdleDoneCodec,
 };
 use imap_types::{
     auth::AuthenticateData,
-    command::Command,
-    response::{CommandContinuationRequest, Data, Greeting, Response, Status},
+    command::{Command, CommandBody},
+    core::{LiteralMode, Tag, Vec1},
+    extensions::enable::CapabilityEnable,
+    response::{
+        Bye, Capability, Code, CommandContinuationRequest, Data, Greeting, GreetingKind, Response,
+        Status, StatusBody, StatusKind, Tagged,
+    },
     secret::Secret,
+    utils::escape_byte_string,
 };
 use thiserror::Error;
+use tracing::trace;
 
+#[cfg(feature = "deflate")]
+use crate::deflate::Deflate;
 use crate::{
     client_receive::ClientReceiveState,
     client_send::{ClientSendEvent, ClientSendState, ClientSendTermination},
     handle::{Handle, HandleGenerator, HandleGeneratorGenerator, RawHandle},
     receive::{ReceiveError, ReceiveEvent, ReceiveState},
     types::CommandAuthenticate,
-    Interrupt, State,
+    Fairness, Interrupt, Io, LineEnding, State, WireDirection, WireTap,
 };
 
 static HANDLE_GENERATOR_GENERATOR: HandleGeneratorGenerator<CommandHandle> =
@@ -28,6 +38,23 @@ static HANDLE_GENERATOR_GENERATOR: HandleGeneratorGenerator<CommandHandle> =
 #[non_exhaustive]
 pub struct Options {
     pub crlf_relaxed: bool,
+    /// Order in which [`Client::next`] progresses sending and receiving.
+    pub fairness: Fairness,
+    /// Line ending used for sent commands.
+    pub line_ending: LineEnding,
+    /// Default strategy for sending literals.
+    ///
+    /// Can be overridden per command via [`Client::enqueue_command_with_literal_strategy`].
+    pub literal_strategy: LiteralStrategy,
+    /// Emit a `trace` event with the raw bytes of each sent and received chunk.
+    ///
+    /// Unlike the `io/read/raw` and `io/write/raw` events of `Stream` (debug builds only), the
+    /// bytes are neither encrypted nor compressed and are also emitted in release builds. This is
+    /// useful for debugging interoperability issues. Use [`Client::set_wire_tap`] for processing
+    /// the bytes programmatically instead.
+    ///
+    /// Note: The bytes might contain secrets, e.g., passwords.
+    pub trace_raw_bytes: bool,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -36,14 +63,69 @@ impl Default for Options {
         Self {
             // Lean towards conformity
             crlf_relaxed: false,
+            fairness: Fairness::default(),
+            line_ending: LineEnding::default(),
+            literal_strategy: LiteralStrategy::default(),
+            trace_raw_bytes: false,
+        }
+    }
+}
+
+/// Strategy for sending literals.
+///
+/// Non-synchronizing literals (RFC 7888) save a round trip per literal because the client
+/// doesn't wait for a continuation request. Whether the server supports them (LITERAL+ or
+/// LITERAL-) is detected from the capabilities announced by the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LiteralStrategy {
+    /// Always send synchronizing literals.
+    AlwaysSync,
+    /// Send non-synchronizing literals if supported by the server.
+    ///
+    /// With LITERAL- only literals up to 4096 bytes are sent as non-synchronizing literals.
+    #[default]
+    NonSyncIfSupported,
+    /// Like [`LiteralStrategy::NonSyncIfSupported`], but only for literals up to the given
+    /// number of bytes.
+    ///
+    /// Useful for sending small literals (e.g. in LOGIN) without round trip, while large
+    /// literals (e.g. in APPEND) can still be rejected by the server before being sent.
+    NonSyncUpTo(u32),
+}
+
+impl LiteralStrategy {
+    /// Returns the max length of literals that are sent as non-synchronizing literals.
+    ///
+    /// `supported_limit` is the max length supported by the server.
+    pub(crate) fn non_sync_literal_limit(self, supported_limit: Option<u32>) -> Option<u32> {
+        match self {
+            Self::AlwaysSync => None,
+            Self::NonSyncIfSupported => supported_limit,
+            Self::NonSyncUpTo(limit) => supported_limit.map(|supported| supported.min(limit)),
         }
     }
 }
 
 pub struct Client {
+    options: Options,
     handle_generator: HandleGenerator<CommandHandle>,
     send_state: ClientSendState,
     receive_state: ClientReceiveState,
+    /// Whether the next call of [`Client::next`] should try receiving before sending.
+    receive_first: bool,
+    /// Kind of the received [`Greeting`], `None` if not received yet.
+    greeting_kind: Option<GreetingKind>,
+    /// STARTTLS command that was sent but not answered yet.
+    starttls: Option<(CommandHandle, Tag<'static>)>,
+    /// Capabilities most recently announced by the server.
+    capabilities: Option<Vec1<Capability<'static>>>,
+    /// Extensions enabled via ENABLE.
+    enabled: Vec<CapabilityEnable<'static>>,
+    /// Callback for the raw bytes, see [`Client::set_wire_tap`].
+    wire_tap: Option<WireTap>,
+    /// Compression layer, set after COMPRESS=DEFLATE was negotiated.
+    #[cfg(feature = "deflate")]
+    deflate: Option<Deflate>,
 }
 
 impl Client {
@@ -52,6 +134,7 @@ impl Client {
             CommandCodec::default(),
             AuthenticateDataCodec::default(),
             IdleDoneCodec::default(),
+            options.line_ending,
         );
 
         let receive_state = ClientReceiveState::Greeting(ReceiveState::new(
@@ -61,31 +144,99 @@ impl Client {
         ));
 
         Self {
+            options,
             handle_generator: HANDLE_GENERATOR_GENERATOR.generate(),
             send_state,
             receive_state,
+            receive_first: false,
+            greeting_kind: None,
+            starttls: None,
+            capabilities: None,
+            enabled: Vec::new(),
+            wire_tap: None,
+            #[cfg(feature = "deflate")]
+            deflate: None,
         }
     }
 
+    /// Sets a callback that is called with the raw bytes of each sent and received chunk.
+    ///
+    /// In contrast to [`Options::trace_raw_bytes`], the bytes can be processed
+    /// programmatically, e.g., for recording a session. Sent bytes are passed when they are
+    /// returned via [`Io::Output`], received bytes when they are enqueued.
+    ///
+    /// Note: The bytes might contain secrets, e.g., passwords.
+    pub fn set_wire_tap(
+        &mut self,
+        wire_tap: impl FnMut(WireDirection, &[u8]) + Send + Sync + 'static,
+    ) {
+        self.wire_tap = Some(Box::new(wire_tap));
+    }
+
     /// Enqueues the [`Command`] for being sent to the client.
     ///
     /// The [`Command`] is not sent immediately but during one of the next calls of
     /// [`Client::next`]. All [`Command`]s are sent in the same order they have been
     /// enqueued.
     pub fn enqueue_command(&mut self, command: Command<'static>) -> CommandHandle {
+        self.enqueue_command_with_literal_strategy(command, self.options.literal_strategy)
+    }
+
+    /// Like [`Client::enqueue_command`], but overrides [`Options::literal_strategy`] for this
+    /// [`Command`].
+    pub fn enqueue_command_with_literal_strategy(
+        &mut self,
+        command: Command<'static>,
+        literal_strategy: LiteralStrategy,
+    ) -> CommandHandle {
         let handle = self.handle_generator.generate();
-        self.send_state.enqueue_command(handle, command);
+        self.send_state
+            .enqueue_command(handle, command, literal_strategy);
         handle
     }
 
+    /// Encodes the [`Command`] exactly like [`Client`] would send it, without sending it.
+    ///
+    /// The fragments are returned in the order they would be sent. Note that the [`Client`]
+    /// waits for the server's continuation request before sending a synchronizing literal.
+    /// This is useful for logging, debugging, or previewing what a [`Command`] will do.
+    pub fn encode_command(&self, command: &Command) -> Vec<CommandFragment> {
+        self.send_state
+            .encode_command(command, self.options.literal_strategy)
+            .into_iter()
+            .map(|fragment| match fragment {
+                Fragment::Line { data } => CommandFragment::Line { data },
+                Fragment::Literal { data, mode } => CommandFragment::Literal { data, mode },
+            })
+            .collect()
+    }
+
+    /// Returns the kind of the received [`Greeting`], `None` if not received yet.
+    ///
+    /// [`GreetingKind::PreAuth`] means the connection is already authenticated, so there is no
+    /// need to authenticate. [`GreetingKind::Bye`] means the server rejected the connection and
+    /// is going to close it.
+    pub fn greeting_kind(&self) -> Option<GreetingKind> {
+        self.greeting_kind
+    }
+
     fn progress_send(&mut self) -> Result<Option<Event>, Interrupt<Error>> {
         // Abort if we didn't received the greeting yet
         if let ClientReceiveState::Greeting(_) = &self.receive_state {
             return Ok(None);
         }
 
+        // Abort if further commands would be sent before the TLS handshake
+        if self.starttls.is_some() {
+            return Ok(None);
+        }
+
         match self.send_state.next() {
             Ok(Some(ClientSendEvent::Command { handle, command })) => {
+                if let CommandBody::StartTLS = command.body {
+                    self.starttls = Some((handle, command.tag.clone()));
+                }
+
                 Ok(Some(Event::CommandSent { handle, command }))
             }
             Ok(Some(ClientSendEvent::Authenticate { handle })) => {
@@ -98,7 +249,18 @@ impl Client {
                 Ok(Some(Event::IdleDoneSent { handle }))
             }
             Ok(None) => Ok(None),
-            Err(Interrupt::Io(io)) => Err(Interrupt::Io(io)),
+            Err(Interrupt::Io(io)) => {
+                if let Io::Output(bytes) = &io {
+                    if self.options.trace_raw_bytes {
+                        trace!(data = escape_byte_string(bytes), "client/write/raw");
+                    }
+                    if let Some(wire_tap) = &mut self.wire_tap {
+                        wire_tap(WireDirection::Sent, bytes);
+                    }
+                }
+
+                Err(Interrupt::Io(io))
+            }
             Err(Interrupt::Error(_)) => unreachable!(),
         }
     }
@@ -111,6 +273,10 @@ impl Client {
                         Ok(ReceiveEvent::DecodingSuccess(greeting)) => {
                             state.finish_message();
                             self.receive_state.change_state();
+                            if let Some(Code::Capability(capabilities)) = &greeting.code {
+                                self.update_capabilities(capabilities);
+                            }
+                            self.greeting_kind = Some(greeting.kind);
                             break Some(Event::GreetingReceived { greeting });
                         }
                         Err(Interrupt::Io(io)) => return Err(Interrupt::Io(io)),
@@ -132,6 +298,10 @@ impl Client {
                             // Unreachable because message limit is not set
                             unreachable!()
                         }
+                        Err(Interrupt::Error(ReceiveError::DiscardedLiteralFound { .. })) => {
+                            // Unreachable because messages are never discarded with literals
+                            unreachable!()
+                        }
                     }
                 }
                 ClientReceiveState::Response(state) => {
@@ -166,10 +336,69 @@ impl Client {
                             // Unreachable because message limit is not set
                             unreachable!()
                         }
+                        Err(Interrupt::Error(ReceiveError::DiscardedLiteralFound { .. })) => {
+                            // Unreachable because messages are never discarded with literals
+                            unreachable!()
+                        }
                     };
 
                     match response {
                         Response::Status(status) => {
+                            if let Some(Code::Capability(capabilities)) = status_code(&status) {
+                                self.update_capabilities(capabilities);
+                            }
+
+                            if let (
+                                Some((handle, _)),
+                                Status::Untagged(StatusBody {
+                                    kind: StatusKind::Bad,
+                                    ..
+                                })
+                                | Status::Bye(_),
+                            ) = (&self.starttls, &status)
+                            {
+                                // The tagged status for STARTTLS might never arrive, don't
+                                // block sending forever
+                                let handle = *handle;
+                                self.starttls = None;
+
+                                break Some(Event::StartTlsAborted { handle, status });
+                            }
+
+                            if let (
+                                Some((handle, tag)),
+                                Status::Tagged(Tagged {
+                                    tag: status_tag,
+                                    body: StatusBody { kind, .. },
+                                    ..
+                                }),
+                            ) = (&self.starttls, &status)
+                            {
+                                if tag == status_tag {
+                                    let handle = *handle;
+                                    let kind = *kind;
+                                    self.starttls = None;
+
+                                    if kind == StatusKind::Ok {
+                                        // Bytes received after the status were sent before the
+                                        // TLS handshake and must not be interpreted as part of
+                                        // the TLS session (see RFC 9051, section 11.1)
+                                        if let ClientReceiveState::Response(state) =
+                                            &mut self.receive_state
+                                        {
+                                            state.discard_input();
+                                        }
+
+                                        // The capabilities announced before the TLS handshake
+                                        // must be discarded (see RFC 9051, section 6.2.1)
+                                        self.capabilities = None;
+                                        self.send_state.set_non_sync_literal_limit(None);
+
+                                        break Some(Event::TlsHandshakeRequired { handle, status });
+                                    }
+                                }
+                            }
+
                             let event = if let Some(finish_result) =
                                 self.send_state.maybe_terminate(&status)
                             {
@@ -203,7 +432,18 @@ impl Client {
 
                             break Some(event);
                         }
-                        Response::Data(data) => break Some(Event::DataReceived { data }),
+                        Response::Data(data) => {
+                            match &data {
+                                Data::Capability(capabilities) => {
+                                    self.update_capabilities(capabilities);
+                                }
+                                Data::Enabled { capabilities } => {
+                                    self.update_enabled(capabilities);
+                                }
+                                _ => {}
+                            }
+                            break Some(Event::DataReceived { data });
+                        }
                         Response::CommandContinuationRequest(continuation_request) => {
                             if self.send_state.literal_continue() {
                                 // We received a continuation request that was necessary for
@@ -237,6 +477,49 @@ impl Client {
         Ok(event)
     }
 
+    /// Returns the capabilities most recently announced by the server.
+    ///
+    /// The capabilities are updated from the [`Greeting`], from CAPABILITY response codes, and
+    /// from CAPABILITY data. `None` if the server didn't announce any capabilities yet.
+    ///
+    /// The capabilities are reset after STARTTLS because the server must announce them again
+    /// over the TLS session. Note that they usually change after authentication, so they might
+    /// be outdated until the server announces them again.
+    pub fn capabilities(&self) -> Option<&Vec1<Capability<'static>>> {
+        self.capabilities.as_ref()
+    }
+
+    /// Returns the extensions the server reported as enabled via ENABLED data (RFC 5161).
+    pub fn enabled(&self) -> &[CapabilityEnable<'static>] {
+        &self.enabled
+    }
+
+    /// Updates the state depending on the capabilities announced by the server.
+    fn update_capabilities(&mut self, capabilities: &Vec1<Capability<'static>>) {
+        self.capabilities = Some(capabilities.clone());
+        let capabilities = capabilities.as_ref();
+
+        let non_sync_literal_limit = if capabilities.contains(&Capability::LiteralPlus) {
+            Some(u32::MAX)
+        } else if capabilities.contains(&Capability::LiteralMinus) {
+            // See RFC 7888, section 4
+            Some(4096)
+        } else {
+            None
+        };
+
+        self.send_state
+            .set_non_sync_literal_limit(non_sync_literal_limit);
+    }
+
+    fn update_enabled(&mut self, capabilities: &[CapabilityEnable<'static>]) {
+        for capability in capabilities {
+            if !self.enabled.contains(capability) {
+                self.enabled.push(capability.clone());
+            }
+        }
+    }
+
     pub fn set_authenticate_data(
         &mut self,
         authenticate_data: AuthenticateData<'static>,
@@ -247,11 +530,104 @@ impl Client {
     pub fn set_idle_done(&mut self) -> Option<CommandHandle> {
         self.send_state.set_idle_done()
     }
+
+    /// Starts COMPRESS=DEFLATE (RFC 4978).
+    ///
+    /// Must be called right after [`Client::next`] returned the tagged OK [`Status`] for the
+    /// `COMPRESS DEFLATE` command. All bytes received after the [`Status`] and all bytes sent from
+    /// now on are compressed. Note that the client should not enqueue further commands until the
+    /// `COMPRESS DEFLATE` command was answered.
+    ///
+    /// Does nothing if compression was already started.
+    #[cfg(feature = "deflate")]
+    pub fn start_compression(&mut self) {
+        if self.deflate.is_some() {
+            return;
+        }
+
+        let mut deflate = Deflate::new();
+
+        // The server might have sent compressed bytes right after the status
+        let unseen_input = match &mut self.receive_state {
+            ClientReceiveState::Greeting(state) => state.take_unseen_input(),
+            ClientReceiveState::Response(state) => state.take_unseen_input(),
+            ClientReceiveState::Dummy => unreachable!(),
+        };
+        deflate.enqueue_input(&unseen_input);
+
+        self.deflate = Some(deflate);
+    }
+
+    #[cfg(feature = "deflate")]
+    fn progress_compressed(&mut self) -> Result<Event, Interrupt<Error>> {
+        let Some(deflate) = &mut self.deflate else {
+            return self.progress();
+        };
+
+        let bytes = deflate
+            .decompress()
+            .map_err(|_| Interrupt::Error(Error::MalformedCompressedData))?;
+        self.enqueue_plain_input(&bytes);
+
+        match self.progress() {
+            Err(Interrupt::Io(Io::Output(bytes))) => {
+                // Unwrap: compression can't be stopped
+                let deflate = self.deflate.as_mut().unwrap();
+                Err(Interrupt::Io(Io::Output(deflate.compress(&bytes))))
+            }
+            result => result,
+        }
+    }
+
+    fn enqueue_plain_input(&mut self, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            if self.options.trace_raw_bytes {
+                trace!(data = escape_byte_string(bytes), "client/read/raw");
+            }
+            if let Some(wire_tap) = &mut self.wire_tap {
+                wire_tap(WireDirection::Received, bytes);
+            }
+        }
+
+        match &mut self.receive_state {
+            ClientReceiveState::Greeting(state) => state.enqueue_input(bytes),
+            ClientReceiveState::Response(state) => state.enqueue_input(bytes),
+            ClientReceiveState::Dummy => unreachable!(),
+        }
+    }
+
+    fn progress(&mut self) -> Result<Event, Interrupt<Error>> {
+        loop {
+            if self.receive_first {
+                self.receive_first = false;
+
+                // Sending made progress during the last call, give receiving a chance first
+                match self.progress_receive() {
+                    Ok(Some(event)) => return Ok(event),
+                    Ok(None) | Err(Interrupt::Io(Io::NeedMoreInput)) => (),
+                    Err(interrupt) => return Err(interrupt),
+                }
+            }
+
+            let result = self.progress_send();
+            if !matches!(result, Ok(None)) {
+                self.receive_first = self.options.fairness == Fairness::Alternate;
+            }
+            if let Some(event) = result? {
+                return Ok(event);
+            }
+
+            if let Some(event) = self.progress_receive()? {
+                return Ok(event);
+            }
+        }
+    }
 }
 
 impl Debug for Client {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         f.debug_struct("Client")
+            .field("options", &self.options)
             .field("handle_generator", &self.handle_generator)
             .finish_non_exhaustive()
     }
@@ -262,23 +638,34 @@ impl State for Client {
     type Error = Error;
 
     fn enqueue_input(&mut self, bytes: &[u8]) {
-        match &mut self.receive_state {
-            ClientReceiveState::Greeting(state) => state.enqueue_input(bytes),
-            ClientReceiveState::Response(state) => state.enqueue_input(bytes),
-            ClientReceiveState::Dummy => unreachable!(),
+        #[cfg(feature = "deflate")]
+        if let Some(deflate) = &mut self.deflate {
+            deflate.enqueue_input(bytes);
+            return;
         }
+
+        self.enqueue_plain_input(bytes);
     }
 
     fn next(&mut self) -> Result<Self::Event, Interrupt<Self::Error>> {
-        loop {
-            if let Some(event) = self.progress_send()? {
-                return Ok(event);
-            }
-
-            if let Some(event) = self.progress_receive()? {
-                return Ok(event);
-            }
+        #[cfg(feature = "deflate")]
+        if self.deflate.is_some() {
+            return self.progress_compressed();
         }
+
+        self.progress()
+    }
+}
+
+/// Returns the code of the [`Status`].
+fn status_code<'a>(status: &'a Status<'static>) -> Option<&'a Code<'static>> {
+    match status {
+        Status::Untagged(StatusBody { code, .. })
+        | Status::Tagged(Tagged {
+            body: StatusBody { code, .. },
+            ..
+        })
+        | Status::Bye(Bye { code, .. }) => code.as_ref(),
     }
 }
 
@@ -300,15 +687,47 @@ impl Debug for CommandHandle {
     }
 }
 
+impl CommandHandle {
+    /// Returns whether the handle was generated by the given [`Client`] instance.
+    ///
+    /// Handles are bound to the instance they were generated by. Comparing them with handles of
+    /// another instance, e.g., after a reconnect, is most likely a bug.
+    pub fn belongs_to(&self, client: &Client) -> bool {
+        client.handle_generator.has_generated(self.0)
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl CommandHandle {
+    /// Creates a handle that wasn't generated by a [`Client`].
+    ///
+    /// This allows to construct [`Event`]s for testing code that processes them without running
+    /// a real [`Client`]. The handle compares equal to any other handle with the same IDs.
+    pub fn new_for_testing(generator_id: u64, handle_id: u64) -> Self {
+        Self(RawHandle::new(generator_id, handle_id))
+    }
+}
+
 impl Handle for CommandHandle {
     fn from_raw(handle: RawHandle) -> Self {
         Self(handle)
     }
 }
 
+/// Fragment of an encoded [`Command`], see [`Client::encode_command`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CommandFragment {
+    /// Line including the trailing `\r\n` (or the announcement of the following literal).
+    Line { data: Vec<u8> },
+    /// Data of a literal.
+    Literal { data: Vec<u8>, mode: LiteralMode },
+}
+
 #[derive(Debug)]
 pub enum Event {
     /// [`Greeting`] received.
+    ///
+    /// Check [`Greeting::kind`] (or [`Client::greeting_kind`]) for PREAUTH and BYE greetings.
     GreetingReceived { greeting: Greeting<'static> },
     /// [`Command`] sent completely.
     CommandSent {
@@ -363,6 +782,26 @@ pub enum Event {
     },
     /// DONE sent. Exiting IDLE state.
     IdleDoneSent { handle: CommandHandle },
+    /// Tagged OK [`Status`] for STARTTLS received.
+    ///
+    /// The client user MUST perform the TLS handshake now, before calling [`Client::next`]
+    /// again. All bytes received after the [`Status`] are discarded. Enqueued commands are not
+    /// sent between sending STARTTLS and receiving its [`Status`].
+    TlsHandshakeRequired {
+        /// Handle to the enqueued STARTTLS [`Command`].
+        handle: CommandHandle,
+        status: Status<'static>,
+    },
+    /// Untagged BAD or BYE [`Status`] received while waiting for the [`Status`] for STARTTLS.
+    ///
+    /// The server either couldn't parse STARTTLS or is closing the connection, so the tagged
+    /// [`Status`] might never arrive. STARTTLS is considered failed and the enqueued commands
+    /// are sent again.
+    StartTlsAborted {
+        /// Handle to the enqueued STARTTLS [`Command`].
+        handle: CommandHandle,
+        status: Status<'static>,
+    },
     /// Server [`Data`] received.
     DataReceived { data: Data<'static> },
     /// Server [`Status`] received.
@@ -376,9 +815,13 @@ pub enum Event {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Expected `\\r\\n`, got `\\n`")]
     ExpectedCrlfGotLf { discarded_bytes: Secret<Box<[u8]>> },
     #[error("Received malformed message")]
     MalformedMessage { discarded_bytes: Secret<Box<[u8]>> },
+    #[cfg(feature = "deflate")]
+    #[error("Received malformed compressed data")]
+    MalformedCompressedData,
 }
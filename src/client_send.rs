@@ -13,12 +13,21 @@ use imap_types::{
 };
 use tracing::warn;
 
-use crate::{client::CommandHandle, types::CommandAuthenticate, Interrupt, Io};
+use crate::{
+    client::{CommandHandle, LiteralStrategy},
+    types::CommandAuthenticate,
+    Interrupt, Io, LineEnding,
+};
 
 pub struct ClientSendState {
     command_codec: CommandCodec,
     authenticate_data_codec: AuthenticateDataCodec,
     idle_done_codec: IdleDoneCodec,
+    line_ending: LineEnding,
+    /// Max length of literals that the server accepts as non-synchronizing literals.
+    ///
+    /// `None` if the server doesn't support non-synchronizing literals.
+    non_sync_literal_limit: Option<u32>,
     /// FIFO queue for messages that should be sent next.
     queued_messages: VecDeque<QueuedMessage>,
     /// Message that is currently being sent.
@@ -30,19 +39,56 @@ impl ClientSendState {
         command_codec: CommandCodec,
         authenticate_data_codec: AuthenticateDataCodec,
         idle_done_codec: IdleDoneCodec,
+        line_ending: LineEnding,
     ) -> Self {
         Self {
             command_codec,
             authenticate_data_codec,
             idle_done_codec,
+            line_ending,
+            non_sync_literal_limit: None,
             queued_messages: VecDeque::new(),
             current_message: None,
         }
     }
 
-    pub fn enqueue_command(&mut self, handle: CommandHandle, command: Command<'static>) {
-        self.queued_messages
-            .push_back(QueuedMessage { handle, command });
+    pub fn enqueue_command(
+        &mut self,
+        handle: CommandHandle,
+        command: Command<'static>,
+        literal_strategy: LiteralStrategy,
+    ) {
+        self.queued_messages.push_back(QueuedMessage {
+            handle,
+            command,
+            literal_strategy,
+        });
+    }
+
+    /// Sets the max length of literals that the server accepts as non-synchronizing literals.
+    ///
+    /// Affects all commands that are not being sent yet.
+    pub fn set_non_sync_literal_limit(&mut self, limit: Option<u32>) {
+        self.non_sync_literal_limit = limit;
+    }
+
+    /// Encodes the command like it would be sent, without enqueueing it.
+    pub fn encode_command(
+        &self,
+        command: &Command,
+        literal_strategy: LiteralStrategy,
+    ) -> Vec<Fragment> {
+        let mut fragments: Vec<_> = self
+            .command_codec
+            .encode(command)
+            .map(|fragment| self.line_ending.apply(fragment))
+            .collect();
+
+        if let Some(limit) = literal_strategy.non_sync_literal_limit(self.non_sync_literal_limit) {
+            convert_to_non_sync_literals(&mut fragments, limit);
+        }
+
+        fragments
     }
 
     /// Terminates the current message depending on the received status.
@@ -195,7 +241,10 @@ impl ClientSendState {
         };
 
         // Encode authenticate data
-        let mut fragments = self.authenticate_data_codec.encode(&authenticate_data);
+        let mut fragments = self
+            .authenticate_data_codec
+            .encode(&authenticate_data)
+            .map(|fragment| self.line_ending.apply(fragment));
         // Authenticate data is a single line by definition
         let Some(Fragment::Line {
             data: authenticate_data,
@@ -250,7 +299,10 @@ impl ClientSendState {
         };
 
         // Encode idle done
-        let mut fragments = self.idle_done_codec.encode(&IdleDone);
+        let mut fragments = self
+            .idle_done_codec
+            .encode(&IdleDone)
+            .map(|fragment| self.line_ending.apply(fragment));
         // Idle done is a single line by defintion
         let Some(Fragment::Line {
             data: idle_done, ..
@@ -287,7 +339,11 @@ impl ClientSendState {
                     return Ok(None);
                 };
 
-                queued_message.start(&self.command_codec)
+                queued_message.start(
+                    &self.command_codec,
+                    self.line_ending,
+                    self.non_sync_literal_limit,
+                )
             }
         };
 
@@ -327,14 +383,25 @@ impl ClientSendState {
 struct QueuedMessage {
     handle: CommandHandle,
     command: Command<'static>,
+    literal_strategy: LiteralStrategy,
 }
 
 impl QueuedMessage {
     /// Start the sending process for this message.
-    fn start(self, codec: &CommandCodec) -> CurrentMessage {
+    fn start(
+        self,
+        codec: &CommandCodec,
+        line_ending: LineEnding,
+        non_sync_literal_limit: Option<u32>,
+    ) -> CurrentMessage {
         let handle = self.handle;
         let command = self.command;
-        let mut fragments = codec.encode(&command);
+        let non_sync_literal_limit = self
+            .literal_strategy
+            .non_sync_literal_limit(non_sync_literal_limit);
+        let mut fragments = codec
+            .encode(&command)
+            .map(|fragment| line_ending.apply(fragment));
         let tag = command.tag;
 
         match command.body {
@@ -371,18 +438,55 @@ impl QueuedMessage {
                     activity: IdleActivity::PushingIdle { idle },
                 })
             }
-            body => CurrentMessage::Command(CommandState {
-                handle,
-                command: Command { tag, body },
-                fragments: fragments.collect(),
-                activity: CommandActivity::PushingFragments {
-                    accepted_literal: None,
-                },
-            }),
+            body => {
+                let mut fragments: VecDeque<_> = fragments.collect();
+                if let Some(limit) = non_sync_literal_limit {
+                    convert_to_non_sync_literals(fragments.make_contiguous(), limit);
+                }
+
+                CurrentMessage::Command(CommandState {
+                    handle,
+                    command: Command { tag, body },
+                    fragments,
+                    activity: CommandActivity::PushingFragments {
+                        accepted_literal: None,
+                    },
+                })
+            }
         }
     }
 }
 
+/// Converts synchronizing literals up to the given length to non-synchronizing literals.
+///
+/// This saves a round trip per literal but requires LITERAL+ or LITERAL- (RFC 7888).
+fn convert_to_non_sync_literals(fragments: &mut [Fragment], limit: u32) {
+    for index in 1..fragments.len() {
+        let (lines, literals) = fragments.split_at_mut(index);
+        let (
+            Fragment::Line { data: line },
+            Fragment::Literal {
+                data,
+                mode: mode @ LiteralMode::Sync,
+            },
+        ) = (&mut lines[index - 1], &mut literals[0])
+        else {
+            continue;
+        };
+
+        if data.len() > limit as usize {
+            continue;
+        }
+
+        // The line ends with the literal announcement, e.g., `{5}\r\n`
+        let Some(position) = line.iter().rposition(|byte| *byte == b'}') else {
+            continue;
+        };
+        line.insert(position, b'+');
+        *mode = LiteralMode::NonSync;
+    }
+}
+
 /// Currently being sent message.
 enum CurrentMessage {
     /// Sending state of regular command.
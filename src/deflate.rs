@@ -0,0 +1,119 @@
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+/// Compression layer for COMPRESS=DEFLATE (RFC 4978).
+///
+/// Compresses the output and decompresses the input of a [`Client`](crate::client::Client) or
+/// [`Server`](crate::server::Server) after the compression was negotiated.
+pub struct Deflate {
+    compress: Compress,
+    decompress: Decompress,
+    /// Compressed input bytes that weren't decompressed yet.
+    input: Vec<u8>,
+}
+
+impl Deflate {
+    pub fn new() -> Self {
+        Self {
+            // RFC 4978 requires raw DEFLATE (RFC 1951) without zlib header
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+            input: Vec::new(),
+        }
+    }
+
+    pub fn enqueue_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+
+    /// Decompresses all enqueued input bytes.
+    pub fn decompress(&mut self) -> Result<Vec<u8>, DeflateError> {
+        let mut output = Vec::with_capacity(self.input.len() * 2 + 64);
+        let mut consumed = 0;
+
+        loop {
+            let total_in = self.decompress.total_in();
+            let total_out = self.decompress.total_out();
+            let status = self
+                .decompress
+                .decompress_vec(&self.input[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|_| DeflateError)?;
+            consumed += (self.decompress.total_in() - total_in) as usize;
+
+            if status == Status::StreamEnd {
+                // RFC 4978 doesn't allow to end the compression
+                return Err(DeflateError);
+            }
+
+            // The decompressor might hold back output that didn't fit into the output buffer,
+            // even if all input bytes were consumed
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity());
+                continue;
+            }
+
+            // All input bytes were decompressed or the remaining bytes can't be decompressed yet
+            if consumed == self.input.len()
+                || (self.decompress.total_in() == total_in
+                    && self.decompress.total_out() == total_out)
+            {
+                break;
+            }
+        }
+
+        self.input.drain(..consumed);
+
+        Ok(output)
+    }
+
+    /// Compresses the output bytes.
+    ///
+    /// The compressed bytes are flushed so that the other side is able to decompress
+    /// them without waiting for more bytes.
+    pub fn compress(&mut self, mut bytes: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(bytes.len() / 2 + 64);
+
+        loop {
+            let total_in = self.compress.total_in();
+            self.compress
+                .compress_vec(bytes, &mut output, FlushCompress::Sync)
+                // Compressing into an in-memory buffer doesn't fail
+                .unwrap();
+            bytes = &bytes[(self.compress.total_in() - total_in) as usize..];
+
+            // The flush is complete when the output buffer wasn't filled up
+            if bytes.is_empty() && output.len() < output.capacity() {
+                break;
+            }
+
+            output.reserve(output.capacity());
+        }
+
+        output
+    }
+}
+
+/// The received bytes are not valid DEFLATE data.
+#[derive(Debug)]
+pub struct DeflateError;
+
+#[cfg(test)]
+mod tests {
+    use super::Deflate;
+
+    #[test]
+    fn large_data_is_decompressed_at_once() {
+        // Compresses very well, so the decompressed data is much larger than the output buffer
+        // reserved for the compressed input
+        let data = b"* 1 FETCH (FLAGS (\\Seen))\r\n".repeat(1000);
+
+        let mut compressor = Deflate::new();
+        let mut decompressor = Deflate::new();
+
+        let compressed = compressor.compress(&data);
+        assert!(compressed.len() * 4 < data.len());
+
+        decompressor.enqueue_input(&compressed);
+        assert_eq!(decompressor.decompress().unwrap(), data);
+        assert_eq!(decompressor.decompress().unwrap(), b"");
+    }
+}
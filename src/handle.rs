@@ -15,6 +15,14 @@ pub struct RawHandle {
 }
 
 impl RawHandle {
+    #[cfg(feature = "test-util")]
+    pub fn new(generator_id: u64, handle_id: u64) -> Self {
+        Self {
+            generator_id,
+            handle_id,
+        }
+    }
+
     pub fn generator_id(&self) -> u64 {
         self.generator_id
     }
@@ -43,6 +51,11 @@ impl<H: Handle> Debug for HandleGenerator<H> {
 }
 
 impl<H: Handle> HandleGenerator<H> {
+    /// Returns whether the handle was generated by this generator instance.
+    pub fn has_generated(&self, raw_handle: RawHandle) -> bool {
+        raw_handle.generator_id == self.generator_id && raw_handle.handle_id < self.next_handle_id
+    }
+
     pub fn generate(&mut self) -> H {
         let handle_id = self.next_handle_id;
         self.next_handle_id = self.next_handle_id.wrapping_add(1);
@@ -109,4 +122,26 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn generators_recognize_their_handles() {
+        let gen_gen = HandleGeneratorGenerator::<TestHandle>::new();
+        let mut gen1 = gen_gen.generate();
+        let mut gen2 = gen_gen.generate();
+
+        let handle1 = gen1.generate();
+        let handle2 = gen2.generate();
+
+        assert!(gen1.has_generated(handle1.0));
+        assert!(!gen1.has_generated(handle2.0));
+        assert!(gen2.has_generated(handle2.0));
+        assert!(!gen2.has_generated(handle1.0));
+
+        // Not generated yet
+        let next_handle = RawHandle {
+            generator_id: handle1.0.generator_id,
+            handle_id: handle1.0.handle_id + 1,
+        };
+        assert!(!gen1.has_generated(next_handle));
+    }
 }
@@ -1,8 +1,12 @@
 #![forbid(unsafe_code)]
 
+use imap_codec::encode::Fragment;
+
 pub mod client;
 mod client_receive;
 mod client_send;
+#[cfg(feature = "deflate")]
+mod deflate;
 mod handle;
 mod receive;
 pub mod server;
@@ -53,6 +57,66 @@ impl<F: State> State for &mut F {
     }
 }
 
+/// Order in which [`State::next`] progresses sending and receiving.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Fairness {
+    /// Always progress sending before receiving.
+    ///
+    /// Received messages are only processed when there is nothing left to send. This might
+    /// delay the processing of received messages when many messages are enqueued.
+    #[default]
+    SendFirst,
+    /// Alternate between sending and receiving.
+    ///
+    /// After sending made progress, the next call first tries to process a received message.
+    Alternate,
+}
+
+/// Line ending used for sent messages.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum LineEnding {
+    /// Terminate lines with `\r\n` as required by IMAP.
+    #[default]
+    Crlf,
+    /// Terminate lines with a bare `\n`.
+    ///
+    /// This violates IMAP and is only useful for testing how peers deal with bare LFs. Literals
+    /// are sent unmodified.
+    Lf,
+}
+
+impl LineEnding {
+    /// Applies the line ending to an encoded fragment.
+    pub(crate) fn apply(self, fragment: Fragment) -> Fragment {
+        match (self, fragment) {
+            (Self::Lf, Fragment::Line { mut data }) if data.ends_with(b"\r\n") => {
+                data.remove(data.len() - 2);
+                Fragment::Line { data }
+            }
+            (_, fragment) => fragment,
+        }
+    }
+}
+
+/// Direction of the bytes passed to a wire tap.
+///
+/// See [`Client::set_wire_tap`](client::Client::set_wire_tap) and
+/// [`Server::set_wire_tap`](server::Server::set_wire_tap).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WireDirection {
+    /// Bytes were sent to the other side.
+    Sent,
+    /// Bytes were received from the other side.
+    Received,
+}
+
+/// Callback that is called with the raw bytes of each sent and received chunk.
+///
+/// Must be `Sync` so that [`Client`](client::Client) and [`Server`](server::Server) stay `Sync`.
+pub(crate) type WireTap = Box<dyn FnMut(WireDirection, &[u8]) + Send + Sync>;
+
 /// State progression was interrupted by an event that needs to be handled externally.
 #[must_use = "If state progression is interrupted the interrupt must be handled. Ignoring this might result in a deadlock on IMAP level"]
 #[derive(Clone, Debug, Eq, PartialEq)]
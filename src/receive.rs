@@ -43,6 +43,12 @@ impl<C> ReceiveState<C> {
         self.read_buffer.extend(bytes);
     }
 
+    /// Removes the bytes that weren't seen yet from the read buffer.
+    #[cfg(feature = "deflate")]
+    pub fn take_unseen_input(&mut self) -> BytesMut {
+        self.read_buffer.split_off(self.seen_bytes)
+    }
+
     pub fn start_literal(&mut self, length: u32) {
         self.next_fragment = NextFragment::Literal { length };
         self.read_buffer.reserve(length as usize);
@@ -60,6 +66,24 @@ impl<C> ReceiveState<C> {
         discarded_bytes
     }
 
+    /// Discards the current message and all bytes received after it.
+    pub fn discard_input(&mut self) {
+        self.read_buffer.clear();
+        self.seen_bytes = 0;
+        self.next_fragment = NextFragment::start_new_line();
+    }
+
+    /// Discards the current message including the announced literal with the given length.
+    ///
+    /// The bytes of the literal and the rest of the message are consumed without being stored
+    /// while receiving the next message. This allows to skip a rejected non-synchronizing
+    /// literal, see RFC 7888, section 4.
+    pub fn discard_message_with_literal(&mut self, length: u32) -> Box<[u8]> {
+        let discarded_bytes = self.discard_message();
+        self.next_fragment = NextFragment::DiscardedLiteral { length };
+        discarded_bytes
+    }
+
     pub fn next(&mut self) -> Result<ReceiveEvent<C>, Interrupt<ReceiveError<C>>>
     where
         C: Decoder,
@@ -74,6 +98,12 @@ impl<C> ReceiveState<C> {
                 NextFragment::Literal { length } => {
                     self.progress_literal(length)?;
                 }
+                NextFragment::DiscardedLiteral { length } => {
+                    self.progress_discarded_literal(length)?;
+                }
+                NextFragment::DiscardedLine => {
+                    self.progress_discarded_line()?;
+                }
             };
         }
     }
@@ -161,6 +191,69 @@ impl<C> ReceiveState<C> {
         Ok(())
     }
 
+    fn progress_discarded_literal(
+        &mut self,
+        literal_length: u32,
+    ) -> Result<(), Interrupt<ReceiveError<C>>>
+    where
+        C: Decoder,
+    {
+        // Discarded bytes are never part of a message
+        debug_assert_eq!(self.seen_bytes, 0);
+
+        let discarded_bytes = self.read_buffer.len().min(literal_length as usize);
+        self.read_buffer.advance(discarded_bytes);
+
+        // Unwrap: `discarded_bytes` is not bigger than `literal_length`
+        let remaining_length = literal_length - u32::try_from(discarded_bytes).unwrap();
+        if remaining_length > 0 {
+            self.next_fragment = NextFragment::DiscardedLiteral {
+                length: remaining_length,
+            };
+            return Err(Interrupt::Io(Io::NeedMoreInput));
+        }
+
+        // The rest of the line belongs to the discarded message as well
+        self.next_fragment = NextFragment::DiscardedLine;
+
+        Ok(())
+    }
+
+    fn progress_discarded_line(&mut self) -> Result<(), Interrupt<ReceiveError<C>>>
+    where
+        C: Decoder,
+    {
+        // Discarded bytes are never part of a message
+        debug_assert_eq!(self.seen_bytes, 0);
+
+        let Some(lf_position) = self.read_buffer.iter().position(|byte| *byte == b'\n') else {
+            // Keep the end of the line because it might announce another literal, e.g., `{5+}\r`
+            let discarded_bytes = self
+                .read_buffer
+                .len()
+                .saturating_sub(MAX_LITERAL_ANNOUNCEMENT_LENGTH);
+            self.read_buffer.advance(discarded_bytes);
+            return Err(Interrupt::Io(Io::NeedMoreInput));
+        };
+
+        match find_literal_announcement(&self.read_buffer[..=lf_position]) {
+            Some(length) => {
+                // The discarded message continues with another literal. The caller decides
+                // whether to discard it as well.
+                self.seen_bytes = lf_position + 1;
+                Err(Interrupt::Error(ReceiveError::DiscardedLiteralFound {
+                    length,
+                }))
+            }
+            None => {
+                // The discarded message is finished
+                self.read_buffer.advance(lf_position + 1);
+                self.next_fragment = NextFragment::start_new_line();
+                Ok(())
+            }
+        }
+    }
+
     fn max_readable_bytes(&self) -> usize {
         let readable_bytes = self.read_buffer.len();
         self.max_message_size
@@ -185,6 +278,13 @@ pub enum ReceiveError<C: Decoder> {
     DecodingFailure(C::Error<'static>),
     ExpectedCrlfGotLf,
     MessageTooLong,
+    /// The rest of a discarded message announces another non-synchronizing literal.
+    ///
+    /// The caller must either discard it via [`ReceiveState::discard_message_with_literal`] or
+    /// stop discarding via [`ReceiveState::discard_message`].
+    DiscardedLiteralFound {
+        length: u32,
+    },
 }
 
 /// Next fragment that will be read...
@@ -199,6 +299,10 @@ enum NextFragment {
     },
     /// ... is a literal with the given length.
     Literal { length: u32 },
+    /// ... is a literal with the given length that will be discarded.
+    DiscardedLiteral { length: u32 },
+    /// ... is the rest of a line that will be discarded.
+    DiscardedLine,
 }
 
 impl NextFragment {
@@ -232,3 +336,51 @@ fn find_crlf(buf: &[u8], start: usize, crlf_relaxed: bool) -> Option<FindCrlfRes
         expected_crlf_got_lf,
     })
 }
+
+/// Max length of a literal announcement at the end of a line, e.g., `{4294967295+}\r\n`.
+const MAX_LITERAL_ANNOUNCEMENT_LENGTH: usize = 15;
+
+/// Returns the announced literal length if the line ends with a non-synchronizing literal
+/// announcement.
+///
+/// Synchronizing literals are ignored because the client waits for a continuation request
+/// before sending them. The server rejected the message instead, so the literal is never sent.
+///
+/// Parameters:
+/// - `line`: The line including the trailing `\n`.
+fn find_literal_announcement(line: &[u8]) -> Option<u32> {
+    let line = line.strip_suffix(b"\n")?;
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = line.strip_suffix(b"+}")?;
+    let start = line.iter().rposition(|byte| *byte == b'{')? + 1;
+    let digits = &line[start..];
+
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_literal_announcement;
+
+    #[test]
+    fn literal_announcements_are_found() {
+        let tests: [(&[u8], Option<u32>); 8] = [
+            (b"A1 LOGIN {5}\r\n", None),
+            (b" {5+}\r\n", Some(5)),
+            (b"{4294967295+}\n", Some(u32::MAX)),
+            (b"{4294967296+}\r\n", None),
+            (b"{4294967295}\r\n", None),
+            (b" FGHIJ\r\n", None),
+            (b" {}\r\n", None),
+            (b" {5-}\r\n", None),
+        ];
+
+        for (line, expected) in tests {
+            assert_eq!(find_literal_announcement(line), expected);
+        }
+    }
+}
@@ -8,23 +8,27 @@ use imap_codec::{
 use imap_types::{
     auth::AuthenticateData,
     command::{Command, CommandBody},
-    core::{LiteralMode, Tag, Text},
+    core::{IString, LiteralMode, NString, Tag, Text, Vec1},
     extensions::idle::IdleDone,
     response::{
-        CommandContinuationRequest, CommandContinuationRequestBasic, Data, Greeting, Response,
-        Status,
+        Capability, Code, CodeOther, CommandContinuationRequest, CommandContinuationRequestBasic,
+        Data, Greeting, Response, Status, StatusBody, StatusKind, Tagged,
     },
     secret::Secret,
+    utils::escape_byte_string,
 };
 use thiserror::Error;
+use tracing::trace;
 
+#[cfg(feature = "deflate")]
+use crate::deflate::Deflate;
 use crate::{
     handle::{Handle, HandleGenerator, HandleGeneratorGenerator, RawHandle},
     receive::{ReceiveError, ReceiveEvent, ReceiveState},
     server_receive::{NextExpectedMessage, ServerReceiveState},
     server_send::{ServerSendEvent, ServerSendState},
     types::CommandAuthenticate,
-    Interrupt, State,
+    Fairness, Interrupt, Io, LineEnding, State, WireDirection, WireTap,
 };
 
 static HANDLE_GENERATOR_GENERATOR: HandleGeneratorGenerator<ResponseHandle> =
@@ -42,10 +46,63 @@ pub struct Options {
     /// APPEND command. However, this might change in the future. Note that
     /// `max_literal_size < max_command_size` must hold.
     pub max_literal_size: u32,
+    /// Max total size of the rejected non-synchronizing literals that are discarded.
+    ///
+    /// The client doesn't wait for the server before sending a non-synchronizing literal. So the
+    /// server can't prevent that a literal bigger than `max_literal_size` is sent. Instead, it
+    /// rejects the command and discards the literal while receiving the next command, see
+    /// RFC 7888, section 4. The sizes of all discarded literals of the connection are summed up.
+    /// A literal that would exceed this limit is not discarded and the connection can't be
+    /// recovered, see [`Error::NonSyncLiteralTooLong`].
+    pub max_discarded_literal_size: u32,
     /// Max command size that can be parsed by the server.
     ///
     /// Bigger commands raise an error.
     pub max_command_size: u32,
+    /// Order in which [`Server::next`] progresses sending and receiving.
+    pub fairness: Fairness,
+    /// Line ending used for sent responses.
+    pub line_ending: LineEnding,
+    /// Emit a `trace` event with the raw bytes of each sent and received chunk.
+    ///
+    /// Unlike the `io/read/raw` and `io/write/raw` events of `Stream` (debug builds only), the
+    /// bytes are neither encrypted nor compressed and are also emitted in release builds. This is
+    /// useful for debugging interoperability issues. Use [`Server::set_wire_tap`] for processing
+    /// the bytes programmatically instead.
+    ///
+    /// Note: The bytes might contain secrets, e.g., passwords.
+    pub trace_raw_bytes: bool,
+    /// Capabilities announced by the server.
+    ///
+    /// If set, the server announces the capabilities in the greeting (unless the greeting
+    /// already has a code), answers CAPABILITY commands automatically, and accepts
+    /// non-synchronizing literals only if LITERAL+ or LITERAL- is announced. Use
+    /// [`Server::set_capabilities`] to change the capabilities, e.g., after authentication.
+    ///
+    /// If STARTTLS is announced, STARTTLS commands are returned as
+    /// [`Event::StartTlsCommandReceived`] and the connection is prepared for the TLS handshake,
+    /// see [`Server::starttls_accept`]. Otherwise, they are returned as [`Event::CommandReceived`].
+    ///
+    /// If not set, the server user needs to handle CAPABILITY commands.
+    pub capabilities: Option<Vec1<Capability<'static>>>,
+    /// ID parameters of the server (RFC 2971).
+    ///
+    /// If set, the server answers ID commands automatically and emits [`Event::IdReceived`]
+    /// with the parameters of the client. An empty list is sent as `NIL`.
+    ///
+    /// If not set, the server user needs to handle ID commands.
+    pub id_parameters: Option<Vec<(IString<'static>, NString<'static>)>>,
+    /// Max number of pipelined commands that were received but not yet answered.
+    ///
+    /// A command counts as pending from [`Event::CommandReceived`] until a tagged [`Status`]
+    /// with the same tag is enqueued. Commands received while the limit is reached are rejected
+    /// with a tagged `NO [LIMIT]` automatically (RFC 5530), see [`Event::CommandRateLimited`].
+    /// This protects the server from clients that pipeline more commands than it is able to
+    /// process.
+    ///
+    /// Note: Limiting the commands per time interval requires a clock and is therefore left to
+    /// the server user.
+    pub max_pending_commands: Option<u32>,
     literal_accept_ccr: CommandContinuationRequest<'static>,
     literal_reject_ccr: CommandContinuationRequest<'static>,
 }
@@ -60,6 +117,14 @@ impl Default for Options {
             // Must be bigger than `max_literal_size`.
             // 64 KiB is used by Dovecot.
             max_command_size: (25 * 1024 * 1024) + (64 * 1024),
+            // Discarding doesn't need memory but costs bandwidth.
+            max_discarded_literal_size: 100 * 1024 * 1024,
+            fairness: Fairness::default(),
+            line_ending: LineEnding::default(),
+            trace_raw_bytes: false,
+            capabilities: None,
+            id_parameters: None,
+            max_pending_commands: None,
             // Short unmeaning text
             literal_accept_ccr: CommandContinuationRequest::basic(None, Text::unvalidated("..."))
                 .unwrap(),
@@ -90,6 +155,31 @@ impl Options {
         }
     }
 
+    /// Returns the max size of non-synchronizing literals accepted by the server.
+    fn max_non_sync_literal_size(&self) -> u32 {
+        let Some(capabilities) = &self.capabilities else {
+            return self.max_literal_size;
+        };
+
+        if capabilities.as_ref().contains(&Capability::LiteralPlus) {
+            self.max_literal_size
+        } else if capabilities.as_ref().contains(&Capability::LiteralMinus) {
+            // See RFC 7888, section 4
+            self.max_literal_size.min(4096)
+        } else {
+            // Client must not send non-synchronizing literals
+            0
+        }
+    }
+
+    /// Returns whether STARTTLS is announced, i.e., whether STARTTLS commands are handled by
+    /// the server via [`Event::StartTlsCommandReceived`].
+    fn starttls_announced(&self) -> bool {
+        self.capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.as_ref().contains(&Capability::StartTls))
+    }
+
     pub fn literal_reject_text(&self) -> &Text {
         match self.literal_reject_ccr {
             CommandContinuationRequest::Basic(ref basic) => basic.text(),
@@ -115,13 +205,34 @@ pub struct Server {
     handle_generator: HandleGenerator<ResponseHandle>,
     send_state: ServerSendState,
     receive_state: ServerReceiveState,
+    /// Whether the next call of [`Server::next`] should try receiving before sending.
+    receive_first: bool,
+    /// Tag of the STARTTLS command, set until it was accepted or rejected.
+    starttls_tag: Option<Tag<'static>>,
+    /// Handle of the tagged OK [`Status`] for STARTTLS, set until it was sent.
+    starttls_handle: Option<ResponseHandle>,
+    /// Total size of the rejected non-synchronizing literals that were discarded.
+    discarded_literal_size: u64,
+    /// Tags of the received commands that were not answered yet.
+    pending_tags: Vec<Tag<'static>>,
+    /// Callback for the raw bytes, see [`Server::set_wire_tap`].
+    wire_tap: Option<WireTap>,
+    /// Compression layer, set after COMPRESS=DEFLATE was negotiated.
+    #[cfg(feature = "deflate")]
+    deflate: Option<Deflate>,
 }
 
 impl Server {
-    pub fn new(options: Options, greeting: Greeting<'static>) -> Self {
-        let mut send_state =
-            ServerSendState::new(GreetingCodec::default(), ResponseCodec::default());
+    pub fn new(options: Options, mut greeting: Greeting<'static>) -> Self {
+        let mut send_state = ServerSendState::new(
+            GreetingCodec::default(),
+            ResponseCodec::default(),
+            options.line_ending,
+        );
 
+        if let (None, Some(capabilities)) = (&greeting.code, &options.capabilities) {
+            greeting.code = Some(Code::Capability(capabilities.clone()));
+        }
         send_state.enqueue_greeting(greeting);
 
         let receive_state = ServerReceiveState::Command(ReceiveState::new(
@@ -135,9 +246,44 @@ impl Server {
             handle_generator: HANDLE_GENERATOR_GENERATOR.generate(),
             send_state,
             receive_state,
+            receive_first: false,
+            starttls_tag: None,
+            starttls_handle: None,
+            discarded_literal_size: 0,
+            pending_tags: Vec::new(),
+            wire_tap: None,
+            #[cfg(feature = "deflate")]
+            deflate: None,
         }
     }
 
+    /// Sets a callback that is called with the raw bytes of each sent and received chunk.
+    ///
+    /// In contrast to [`Options::trace_raw_bytes`], the bytes can be processed
+    /// programmatically, e.g., for recording a session. Sent bytes are passed when they are
+    /// returned via [`Io::Output`], received bytes when they are enqueued.
+    ///
+    /// Note: The bytes might contain secrets, e.g., passwords.
+    pub fn set_wire_tap(
+        &mut self,
+        wire_tap: impl FnMut(WireDirection, &[u8]) + Send + Sync + 'static,
+    ) {
+        self.wire_tap = Some(Box::new(wire_tap));
+    }
+
+    /// Returns the capabilities announced by the server, see [`Options::capabilities`].
+    pub fn capabilities(&self) -> Option<&Vec1<Capability<'static>>> {
+        self.options.capabilities.as_ref()
+    }
+
+    /// Changes the capabilities announced by the server, see [`Options::capabilities`].
+    ///
+    /// Affects all CAPABILITY commands and literals that are received from now on. Note that
+    /// the capabilities are not sent to the client immediately.
+    pub fn set_capabilities(&mut self, capabilities: Option<Vec1<Capability<'static>>>) {
+        self.options.capabilities = capabilities;
+    }
+
     /// Enqueues the [`Data`] response for being sent to the client.
     ///
     /// The response is not sent immediately but during one of the next calls of
@@ -156,6 +302,12 @@ impl Server {
     /// [`Server::next`]. All responses are sent in the same order they have been
     /// enqueued.
     pub fn enqueue_status(&mut self, status: Status<'static>) -> ResponseHandle {
+        if let Status::Tagged(Tagged { tag, .. }) = &status {
+            if let Some(index) = self.pending_tags.iter().position(|pending| pending == tag) {
+                self.pending_tags.remove(index);
+            }
+        }
+
         let handle = self.handle_generator.generate();
         self.send_state
             .enqueue_response(Some(handle), Response::Status(status));
@@ -189,18 +341,44 @@ impl Server {
                 handle: Some(handle),
                 response,
             })) => {
+                if self.starttls_handle == Some(handle) {
+                    self.starttls_handle = None;
+
+                    // Plaintext bytes received after STARTTLS must not be interpreted as
+                    // commands after the TLS handshake (see RFC 9051, section 11.1)
+                    if let ServerReceiveState::StartTlsAccept(state) = &mut self.receive_state {
+                        state.discard_input();
+                    }
+                    self.receive_state
+                        .change_state(NextExpectedMessage::Command);
+
+                    return Ok(Some(Event::TlsHandshakeRequired { handle }));
+                }
+
                 // A response was sucessfully sent, inform the caller
                 Ok(Some(Event::ResponseSent { handle, response }))
             }
             Ok(Some(ServerSendEvent::Response { handle: None, .. })) => {
-                // An internally created response was sent, don't inform the caller
-                Ok(None)
+                // An internally created response was sent, don't inform the caller but
+                // continue with the next one, e.g., the status after a CAPABILITY response
+                self.progress_send()
             }
             Ok(_) => {
                 // No progress yet
                 Ok(None)
             }
-            Err(Interrupt::Io(io)) => Err(Interrupt::Io(io)),
+            Err(Interrupt::Io(io)) => {
+                if let Io::Output(bytes) = &io {
+                    if self.options.trace_raw_bytes {
+                        trace!(data = escape_byte_string(bytes), "server/write/raw");
+                    }
+                    if let Some(wire_tap) = &mut self.wire_tap {
+                        wire_tap(WireDirection::Sent, bytes);
+                    }
+                }
+
+                Err(Interrupt::Io(io))
+            }
             Err(Interrupt::Error(_)) => unreachable!(),
         }
     }
@@ -212,6 +390,26 @@ impl Server {
                     Ok(ReceiveEvent::DecodingSuccess(command)) => {
                         state.finish_message();
 
+                        if let Some(max_pending_commands) = self.options.max_pending_commands {
+                            if self.pending_tags.len() >= max_pending_commands as usize {
+                                // Reject the command without involving the server user. The
+                                // LIMIT code tells the client that it hit a server limit instead
+                                // of a failure of the command itself (see RFC 5530, section 3).
+                                let code = Code::Other(CodeOther::unvalidated(b"LIMIT".as_ref()));
+                                // Unwrap: This should never fail because the text is not Base64.
+                                let status = Status::no(
+                                    Some(command.tag.clone()),
+                                    Some(code),
+                                    "Too many pending commands",
+                                )
+                                .unwrap();
+                                self.send_state
+                                    .enqueue_response(None, Response::Status(status));
+
+                                return Ok(Some(Event::CommandRateLimited { command }));
+                            }
+                        }
+
                         match command.body {
                             CommandBody::Authenticate {
                                 mechanism,
@@ -234,51 +432,120 @@ impl Server {
 
                                 Ok(Some(Event::IdleCommandReceived { tag: command.tag }))
                             }
-                            body => Ok(Some(Event::CommandReceived {
-                                command: Command {
-                                    tag: command.tag,
-                                    body,
-                                },
-                            })),
+                            CommandBody::Id { parameters }
+                                if self.options.id_parameters.is_some() =>
+                            {
+                                // Answer the command without involving the server user
+                                let data = Data::Id {
+                                    parameters: self
+                                        .options
+                                        .id_parameters
+                                        .clone()
+                                        .filter(|parameters| !parameters.is_empty()),
+                                };
+                                self.send_state.enqueue_response(None, Response::Data(data));
+
+                                // Unwrap: This should never fail because the text is not Base64.
+                                let status =
+                                    Status::ok(Some(command.tag), None, "ID completed").unwrap();
+                                self.send_state
+                                    .enqueue_response(None, Response::Status(status));
+
+                                Ok(Some(Event::IdReceived { parameters }))
+                            }
+                            CommandBody::StartTLS if self.options.starttls_announced() => {
+                                self.receive_state
+                                    .change_state(NextExpectedMessage::StartTlsAccept);
+                                self.starttls_tag = Some(command.tag.clone());
+
+                                Ok(Some(Event::StartTlsCommandReceived { tag: command.tag }))
+                            }
+                            CommandBody::Capability => match &self.options.capabilities {
+                                Some(capabilities) => {
+                                    // Answer the command without involving the server user
+                                    let data = Data::Capability(capabilities.clone());
+                                    self.send_state.enqueue_response(None, Response::Data(data));
+
+                                    // Unwrap: This should never fail because the text is not Base64.
+                                    let status =
+                                        Status::ok(Some(command.tag), None, "CAPABILITY completed")
+                                            .unwrap();
+                                    self.send_state
+                                        .enqueue_response(None, Response::Status(status));
+
+                                    Ok(None)
+                                }
+                                None => {
+                                    self.pending_tags.push(command.tag.clone());
+
+                                    Ok(Some(Event::CommandReceived {
+                                        command: Command {
+                                            tag: command.tag,
+                                            body: CommandBody::Capability,
+                                        },
+                                    }))
+                                }
+                            },
+                            body => {
+                                self.pending_tags.push(command.tag.clone());
+
+                                Ok(Some(Event::CommandReceived {
+                                    command: Command {
+                                        tag: command.tag,
+                                        body,
+                                    },
+                                }))
+                            }
                         }
                     }
                     Err(Interrupt::Io(io)) => Err(Interrupt::Io(io)),
                     Err(Interrupt::Error(ReceiveError::DecodingFailure(
                         CommandDecodeError::LiteralFound { tag, length, mode },
                     ))) => {
-                        if length > self.options.max_literal_size {
-                            match mode {
-                                LiteralMode::Sync => {
-                                    // Inform the client that the literal was rejected.
+                        let max_literal_size = match mode {
+                            LiteralMode::Sync => self.options.max_literal_size,
+                            LiteralMode::NonSync => self.options.max_non_sync_literal_size(),
+                        };
 
-                                    // Unwrap: This should never fail because the text is not Base64.
-                                    let status = Status::bad(
-                                        Some(tag),
-                                        None,
-                                        self.options.literal_reject_text().to_static(),
-                                    )
-                                    .unwrap();
-                                    self.send_state
-                                        .enqueue_response(None, Response::Status(status));
+                        if length > max_literal_size {
+                            // Inform the client that the literal was rejected.
+
+                            // Unwrap: This should never fail because the text is not Base64.
+                            let status = Status::bad(
+                                Some(tag),
+                                None,
+                                self.options.literal_reject_text().to_static(),
+                            )
+                            .unwrap();
+                            self.send_state
+                                .enqueue_response(None, Response::Status(status));
 
+                            match mode {
+                                LiteralMode::Sync => {
                                     let discarded_bytes = state.discard_message();
 
                                     Err(Interrupt::Error(Error::LiteralTooLong {
                                         discarded_bytes: Secret::new(discarded_bytes),
                                     }))
                                 }
+                                LiteralMode::NonSync
+                                    if self.discarded_literal_size + u64::from(length)
+                                        <= u64::from(self.options.max_discarded_literal_size) =>
+                                {
+                                    // We can't make the client stop sending the literal, so we
+                                    // consume it without saving it (see RFC 7888, section 4).
+                                    self.discarded_literal_size += u64::from(length);
+                                    let discarded_bytes =
+                                        state.discard_message_with_literal(length);
+
+                                    Err(Interrupt::Error(Error::LiteralTooLong {
+                                        discarded_bytes: Secret::new(discarded_bytes),
+                                    }))
+                                }
                                 LiteralMode::NonSync => {
-                                    // TODO: We can't (reliably) make the client stop sending data.
-                                    //       Some actions that come to mind:
-                                    //       * terminate the connection
-                                    //       * act as a "discard server", i.e., consume the full
-                                    //         literal w/o saving it, and answering with `BAD`
-                                    //       * ...
-                                    //
-                                    //       The LITERAL+ RFC has some recommendations.
                                     let discarded_bytes = state.discard_message();
 
-                                    Err(Interrupt::Error(Error::LiteralTooLong {
+                                    Err(Interrupt::Error(Error::NonSyncLiteralTooLong {
                                         discarded_bytes: Secret::new(discarded_bytes),
                                     }))
                                 }
@@ -330,6 +597,26 @@ impl Server {
                             discarded_bytes: Secret::new(discarded_bytes),
                         }))
                     }
+                    Err(Interrupt::Error(ReceiveError::DiscardedLiteralFound { length })) => {
+                        // The rejected command continues with another non-synchronizing literal
+                        let discarded_literal_size =
+                            self.discarded_literal_size + u64::from(length);
+
+                        if discarded_literal_size
+                            <= u64::from(self.options.max_discarded_literal_size)
+                        {
+                            self.discarded_literal_size = discarded_literal_size;
+                            state.discard_message_with_literal(length);
+
+                            Ok(None)
+                        } else {
+                            let discarded_bytes = state.discard_message();
+
+                            Err(Interrupt::Error(Error::NonSyncLiteralTooLong {
+                                discarded_bytes: Secret::new(discarded_bytes),
+                            }))
+                        }
+                    }
                 }
             }
             ServerReceiveState::AuthenticateData(state) => match state.next() {
@@ -358,12 +645,22 @@ impl Server {
                         discarded_bytes: Secret::new(discarded_bytes),
                     }))
                 }
+                Err(Interrupt::Error(ReceiveError::DiscardedLiteralFound { .. })) => {
+                    // Unreachable because literals are only discarded while receiving commands
+                    unreachable!()
+                }
             },
             ServerReceiveState::IdleAccept(_) => {
                 // We don't expect any message until the server user calls
                 // `idle_accept` or `idle_reject`.
                 // TODO: It's strange to return NeedMoreInput here, but it works for now.
-                Err(Interrupt::Io(crate::Io::NeedMoreInput))
+                Err(Interrupt::Io(Io::NeedMoreInput))
+            }
+            ServerReceiveState::StartTlsAccept(_) => {
+                // We don't expect any message until the server user calls
+                // `starttls_accept` or `starttls_reject`. After accepting, the remaining
+                // plaintext bytes are discarded.
+                Err(Interrupt::Io(Io::NeedMoreInput))
             }
             ServerReceiveState::IdleDone(state) => match state.next() {
                 Ok(ReceiveEvent::DecodingSuccess(IdleDone)) => {
@@ -395,6 +692,10 @@ impl Server {
                         discarded_bytes: Secret::new(discarded_bytes),
                     }))
                 }
+                Err(Interrupt::Error(ReceiveError::DiscardedLiteralFound { .. })) => {
+                    // Unreachable because literals are only discarded while receiving commands
+                    unreachable!()
+                }
             },
             ServerReceiveState::Dummy => {
                 unreachable!()
@@ -461,34 +762,152 @@ impl Server {
             Err(status)
         }
     }
-}
 
-impl Debug for Server {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        f.debug_struct("Server")
-            .field("options", &self.options)
-            .field("handle_generator", &self.handle_generator)
-            .finish_non_exhaustive()
+    /// Accepts the STARTTLS command by sending the tagged OK [`Status`].
+    ///
+    /// Must be called after [`Server::next`] returned [`Event::StartTlsCommandReceived`]. The
+    /// [`Status`] must be a tagged OK with the tag of the STARTTLS command. Once the [`Status`]
+    /// was sent, [`Server::next`] returns [`Event::TlsHandshakeRequired`] instead of
+    /// [`Event::ResponseSent`]. All plaintext bytes received after the STARTTLS command are
+    /// discarded at this point.
+    pub fn starttls_accept(
+        &mut self,
+        status: Status<'static>,
+    ) -> Result<ResponseHandle, Status<'static>> {
+        let is_tagged_ok = match (&self.starttls_tag, &status) {
+            (
+                Some(tag),
+                Status::Tagged(Tagged {
+                    tag: status_tag,
+                    body: StatusBody { kind, .. },
+                    ..
+                }),
+            ) => tag == status_tag && *kind == StatusKind::Ok,
+            _ => false,
+        };
+
+        if let (ServerReceiveState::StartTlsAccept(_), true) = (&self.receive_state, is_tagged_ok) {
+            self.starttls_tag = None;
+            let handle = self.enqueue_status(status);
+            self.starttls_handle = Some(handle);
+
+            Ok(handle)
+        } else {
+            Err(status)
+        }
     }
-}
 
-impl State for Server {
-    type Event = Event;
-    type Error = Error;
+    /// Rejects the STARTTLS command by sending the tagged BAD or NO [`Status`].
+    ///
+    /// Must be called after [`Server::next`] returned [`Event::StartTlsCommandReceived`].
+    pub fn starttls_reject(
+        &mut self,
+        status: Status<'static>,
+    ) -> Result<ResponseHandle, Status<'static>> {
+        if let (ServerReceiveState::StartTlsAccept(_), Some(_)) =
+            (&self.receive_state, &self.starttls_tag)
+        {
+            self.starttls_tag = None;
+            let handle = self.enqueue_status(status);
+
+            self.receive_state
+                .change_state(NextExpectedMessage::Command);
+
+            Ok(handle)
+        } else {
+            Err(status)
+        }
+    }
+
+    /// Starts COMPRESS=DEFLATE (RFC 4978).
+    ///
+    /// Must be called right after [`Server::next`] returned [`Event::ResponseSent`] for the
+    /// tagged OK [`Status`] of the `COMPRESS DEFLATE` command. All bytes received and all bytes
+    /// sent from now on are compressed. Note that the server should not enqueue further responses
+    /// until the tagged OK [`Status`] was sent.
+    ///
+    /// Does nothing if compression was already started.
+    #[cfg(feature = "deflate")]
+    pub fn start_compression(&mut self) {
+        if self.deflate.is_some() {
+            return;
+        }
+
+        let mut deflate = Deflate::new();
+
+        // The client might have sent compressed bytes right after the command
+        let unseen_input = match &mut self.receive_state {
+            ServerReceiveState::Command(state) => state.take_unseen_input(),
+            ServerReceiveState::AuthenticateData(state) => state.take_unseen_input(),
+            ServerReceiveState::IdleAccept(state) => state.take_unseen_input(),
+            ServerReceiveState::IdleDone(state) => state.take_unseen_input(),
+            ServerReceiveState::StartTlsAccept(state) => state.take_unseen_input(),
+            ServerReceiveState::Dummy => unreachable!(),
+        };
+        deflate.enqueue_input(&unseen_input);
+
+        self.deflate = Some(deflate);
+    }
+
+    #[cfg(feature = "deflate")]
+    fn progress_compressed(&mut self) -> Result<Event, Interrupt<Error>> {
+        let Some(deflate) = &mut self.deflate else {
+            return self.progress();
+        };
+
+        let bytes = deflate
+            .decompress()
+            .map_err(|_| Interrupt::Error(Error::MalformedCompressedData))?;
+        self.enqueue_plain_input(&bytes);
+
+        match self.progress() {
+            Err(Interrupt::Io(Io::Output(bytes))) => {
+                // Unwrap: compression can't be stopped
+                let deflate = self.deflate.as_mut().unwrap();
+                Err(Interrupt::Io(Io::Output(deflate.compress(&bytes))))
+            }
+            result => result,
+        }
+    }
+
+    fn enqueue_plain_input(&mut self, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            if self.options.trace_raw_bytes {
+                trace!(data = escape_byte_string(bytes), "server/read/raw");
+            }
+            if let Some(wire_tap) = &mut self.wire_tap {
+                wire_tap(WireDirection::Received, bytes);
+            }
+        }
 
-    fn enqueue_input(&mut self, bytes: &[u8]) {
         match &mut self.receive_state {
             ServerReceiveState::Command(state) => state.enqueue_input(bytes),
             ServerReceiveState::AuthenticateData(state) => state.enqueue_input(bytes),
             ServerReceiveState::IdleAccept(state) => state.enqueue_input(bytes),
             ServerReceiveState::IdleDone(state) => state.enqueue_input(bytes),
+            ServerReceiveState::StartTlsAccept(state) => state.enqueue_input(bytes),
             ServerReceiveState::Dummy => unreachable!(),
         }
     }
 
-    fn next(&mut self) -> Result<Self::Event, Interrupt<Self::Error>> {
+    fn progress(&mut self) -> Result<Event, Interrupt<Error>> {
         loop {
-            if let Some(event) = self.progress_send()? {
+            if self.receive_first {
+                self.receive_first = false;
+
+                // Sending made progress during the last call, give receiving a chance first
+                match self.progress_receive() {
+                    Ok(Some(event)) => return Ok(event),
+                    Ok(None) | Err(Interrupt::Io(Io::NeedMoreInput)) => (),
+                    Err(interrupt) => return Err(interrupt),
+                }
+            }
+
+            let result = self.progress_send();
+            if !matches!(result, Ok(None)) {
+                self.receive_first = self.options.fairness == Fairness::Alternate;
+            }
+            if let Some(event) = result? {
                 return Ok(event);
             }
 
@@ -499,6 +918,39 @@ impl State for Server {
     }
 }
 
+impl Debug for Server {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("Server")
+            .field("options", &self.options)
+            .field("handle_generator", &self.handle_generator)
+            .finish_non_exhaustive()
+    }
+}
+
+impl State for Server {
+    type Event = Event;
+    type Error = Error;
+
+    fn enqueue_input(&mut self, bytes: &[u8]) {
+        #[cfg(feature = "deflate")]
+        if let Some(deflate) = &mut self.deflate {
+            deflate.enqueue_input(bytes);
+            return;
+        }
+
+        self.enqueue_plain_input(bytes);
+    }
+
+    fn next(&mut self) -> Result<Self::Event, Interrupt<Self::Error>> {
+        #[cfg(feature = "deflate")]
+        if self.deflate.is_some() {
+            return self.progress_compressed();
+        }
+
+        self.progress()
+    }
+}
+
 /// Handle for enqueued [`Response`].
 ///
 /// This handle can be used to track the sending progress. After a [`Response`] was enqueued via
@@ -518,6 +970,27 @@ impl Debug for ResponseHandle {
     }
 }
 
+impl ResponseHandle {
+    /// Returns whether the handle was generated by the given [`Server`] instance.
+    ///
+    /// Handles are bound to the instance they were generated by. Comparing them with handles of
+    /// another instance, e.g., after a reconnect, is most likely a bug.
+    pub fn belongs_to(&self, server: &Server) -> bool {
+        server.handle_generator.has_generated(self.0)
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl ResponseHandle {
+    /// Creates a handle that wasn't generated by a [`Server`].
+    ///
+    /// This allows to construct [`Event`]s for testing code that processes them without running
+    /// a real [`Server`]. The handle compares equal to any other handle with the same IDs.
+    pub fn new_for_testing(generator_id: u64, handle_id: u64) -> Self {
+        Self(RawHandle::new(generator_id, handle_id))
+    }
+}
+
 impl Handle for ResponseHandle {
     fn from_raw(raw_handle: RawHandle) -> Self {
         Self(raw_handle)
@@ -567,9 +1040,39 @@ pub enum Event {
         tag: Tag<'static>,
     },
     IdleDoneReceived,
+    /// Command received and rejected automatically, see [`Options::max_pending_commands`].
+    ///
+    /// The rejection is sent by the server, the server user doesn't need to answer the command.
+    CommandRateLimited {
+        command: Command<'static>,
+    },
+    /// Command ID received and answered automatically, see [`Options::id_parameters`].
+    IdReceived {
+        /// ID parameters of the client.
+        parameters: Option<Vec<(IString<'static>, NString<'static>)>>,
+    },
+    /// Command STARTTLS received.
+    ///
+    /// Only returned if STARTTLS is announced via [`Options::capabilities`].
+    ///
+    /// Note: The server MUST call [`Server::starttls_accept`] or [`Server::starttls_reject`]
+    /// next. No further commands are received until then.
+    StartTlsCommandReceived {
+        tag: Tag<'static>,
+    },
+    /// Tagged OK [`Status`] for STARTTLS was sent.
+    ///
+    /// The server user MUST perform the TLS handshake now, before calling [`Server::next`]
+    /// again. When using `Stream`, call `Stream::flush` to make sure the [`Status`] was written,
+    /// convert it into a `TcpStream`, and create a new one via `Stream::tls` after the handshake.
+    TlsHandshakeRequired {
+        /// Handle of the tagged OK [`Status`] enqueued via [`Server::starttls_accept`].
+        handle: ResponseHandle,
+    },
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Expected `\\r\\n`, got `\\n`")]
     ExpectedCrlfGotLf { discarded_bytes: Secret<Box<[u8]>> },
@@ -577,6 +1080,17 @@ pub enum Error {
     MalformedMessage { discarded_bytes: Secret<Box<[u8]>> },
     #[error("Literal was rejected because it was too long")]
     LiteralTooLong { discarded_bytes: Secret<Box<[u8]>> },
+    /// Non-synchronizing literal was rejected because it was too long to be discarded.
+    ///
+    /// This happens if discarding it would exceed [`Options::max_discarded_literal_size`].
+    ///
+    /// The client is still sending the literal, so the following bytes can't be interpreted
+    /// reliably. The connection should be closed after the rejection was sent.
+    #[error("Non-synchronizing literal was rejected because it was too long to be discarded")]
+    NonSyncLiteralTooLong { discarded_bytes: Secret<Box<[u8]>> },
     #[error("Command is too long")]
     CommandTooLong { discarded_bytes: Secret<Box<[u8]>> },
+    #[cfg(feature = "deflate")]
+    #[error("Received malformed compressed data")]
+    MalformedCompressedData,
 }
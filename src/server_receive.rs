@@ -7,6 +7,7 @@ pub enum ServerReceiveState {
     AuthenticateData(ReceiveState<AuthenticateDataCodec>),
     IdleAccept(ReceiveState<NoCodec>),
     IdleDone(ReceiveState<IdleDoneCodec>),
+    StartTlsAccept(ReceiveState<NoCodec>),
     // This state is set only temporarily during `ServerReceiveState::change_state`
     Dummy,
 }
@@ -23,6 +24,7 @@ impl ServerReceiveState {
                     Self::AuthenticateData(state) => state.change_codec(codec),
                     Self::IdleAccept(state) => state.change_codec(codec),
                     Self::IdleDone(state) => state.change_codec(codec),
+                    Self::StartTlsAccept(state) => state.change_codec(codec),
                     Self::Dummy => unreachable!(),
                 })
             }
@@ -33,6 +35,7 @@ impl ServerReceiveState {
                     Self::AuthenticateData(state) => state,
                     Self::IdleAccept(state) => state.change_codec(codec),
                     Self::IdleDone(state) => state.change_codec(codec),
+                    Self::StartTlsAccept(state) => state.change_codec(codec),
                     Self::Dummy => unreachable!(),
                 })
             }
@@ -43,6 +46,7 @@ impl ServerReceiveState {
                     Self::AuthenticateData(state) => state.change_codec(codec),
                     Self::IdleAccept(state) => state,
                     Self::IdleDone(state) => state.change_codec(codec),
+                    Self::StartTlsAccept(state) => state.change_codec(codec),
                     Self::Dummy => unreachable!(),
                 })
             }
@@ -53,6 +57,18 @@ impl ServerReceiveState {
                     Self::AuthenticateData(state) => state.change_codec(codec),
                     Self::IdleAccept(state) => state.change_codec(codec),
                     Self::IdleDone(state) => state,
+                    Self::StartTlsAccept(state) => state.change_codec(codec),
+                    Self::Dummy => unreachable!(),
+                })
+            }
+            NextExpectedMessage::StartTlsAccept => {
+                let codec = NoCodec;
+                Self::StartTlsAccept(match old_state {
+                    Self::Command(state) => state.change_codec(codec),
+                    Self::AuthenticateData(state) => state.change_codec(codec),
+                    Self::IdleAccept(state) => state.change_codec(codec),
+                    Self::IdleDone(state) => state.change_codec(codec),
+                    Self::StartTlsAccept(state) => state,
                     Self::Dummy => unreachable!(),
                 })
             }
@@ -67,6 +83,7 @@ pub enum NextExpectedMessage {
     AuthenticateData,
     IdleAccept,
     IdleDone,
+    StartTlsAccept,
 }
 
 /// Dummy codec used for technical reasons when we don't want to receive anything at all.
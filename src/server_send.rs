@@ -6,11 +6,12 @@ use imap_codec::{
 };
 use imap_types::response::{Greeting, Response};
 
-use crate::{server::ResponseHandle, Interrupt, Io};
+use crate::{server::ResponseHandle, Interrupt, Io, LineEnding};
 
 pub struct ServerSendState {
     greeting_codec: GreetingCodec,
     response_codec: ResponseCodec,
+    line_ending: LineEnding,
     // FIFO queue for messages that should be sent next.
     queued_messages: VecDeque<QueuedMessage>,
     // The message that is currently being sent.
@@ -18,10 +19,15 @@ pub struct ServerSendState {
 }
 
 impl ServerSendState {
-    pub fn new(greeting_codec: GreetingCodec, response_codec: ResponseCodec) -> Self {
+    pub fn new(
+        greeting_codec: GreetingCodec,
+        response_codec: ResponseCodec,
+        line_ending: LineEnding,
+    ) -> Self {
         Self {
             greeting_codec,
             response_codec,
+            line_ending,
             queued_messages: VecDeque::new(),
             current_message: None,
         }
@@ -67,6 +73,7 @@ impl ServerSendState {
                     &mut write_buffer,
                     &self.greeting_codec,
                     &self.response_codec,
+                    self.line_ending,
                 );
 
                 self.current_message = Some(current_message);
@@ -95,25 +102,26 @@ impl QueuedMessage {
         write_buffer: &mut Vec<u8>,
         greeting_codec: &GreetingCodec,
         response_codec: &ResponseCodec,
+        line_ending: LineEnding,
     ) -> CurrentMessage {
         match self {
             QueuedMessage::Greeting { greeting } => {
                 let encoded = greeting_codec.encode(&greeting);
-                push_encoded_to_buffer(write_buffer, encoded);
+                push_encoded_to_buffer(write_buffer, encoded, line_ending);
                 CurrentMessage::Greeting { greeting }
             }
             QueuedMessage::Response { handle, response } => {
                 let encoded = response_codec.encode(&response);
-                push_encoded_to_buffer(write_buffer, encoded);
+                push_encoded_to_buffer(write_buffer, encoded, line_ending);
                 CurrentMessage::Response { handle, response }
             }
         }
     }
 }
 
-fn push_encoded_to_buffer(write_buffer: &mut Vec<u8>, encoded: Encoded) {
+fn push_encoded_to_buffer(write_buffer: &mut Vec<u8>, encoded: Encoded, line_ending: LineEnding) {
     for fragment in encoded {
-        let data = match fragment {
+        let data = match line_ending.apply(fragment) {
             Fragment::Line { data } => data,
             // Note: The server doesn't need to wait before sending a literal.
             //       Thus, non-sync literals doesn't make sense here.
@@ -85,6 +85,17 @@ impl Stream {
         Ok(())
     }
 
+    /// Progresses the state until the next event (or error) while doing the necessary I/O.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe, e.g., it can be used in `tokio::select!` or with
+    /// `tokio::time::timeout`. Events are returned without awaiting anything, so no event is
+    /// lost on cancellation. Bytes that were already read or produced by the state are kept in
+    /// the internal buffers and are processed by the next call.
+    ///
+    /// Note that the state might have progressed before the cancellation, e.g., a command might
+    /// be partially written. Thus, the same state must be passed to the next call.
     pub async fn next<F: State>(&mut self, mut state: F) -> Result<F::Event, Error<F::Error>> {
         let event = loop {
             match &mut self.tls {
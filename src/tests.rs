@@ -1,15 +1,21 @@
+use std::sync::{Arc, Mutex};
+
+use imap_codec::{decode::Decoder, CommandCodec};
 use imap_types::{
     auth::AuthMechanism,
+    bounded_static::IntoBoundedStatic,
     command::{Command, CommandBody},
-    core::Tag,
-    response::{Greeting, Status},
+    core::{IString, LiteralMode, NString, Tag, Vec1},
+    extensions::enable::{CapabilityEnable, Utf8Kind},
+    response::{Capability, Greeting, GreetingKind, Status},
 };
 use tokio::net::{TcpListener, TcpStream};
 
 use crate::{
-    client::{self, Client},
+    client::{self, Client, CommandFragment, LiteralStrategy},
     server::{self, Server},
     stream::Stream,
+    Fairness, Interrupt, Io, LineEnding, State, WireDirection,
 };
 
 #[tokio::test]
@@ -79,3 +85,619 @@ async fn self_test() {
         }
     }
 }
+
+#[test]
+fn client_alternates_between_sending_and_receiving() {
+    let options = client::Options {
+        fairness: Fairness::Alternate,
+        ..Default::default()
+    };
+    let mut client = Client::new(options);
+
+    client.enqueue_input(b"* OK ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+
+    client.enqueue_command(Command::new("A1", CommandBody::Noop).unwrap());
+    client.enqueue_command(Command::new("A2", CommandBody::Noop).unwrap());
+    client.enqueue_input(b"* 1 EXISTS\r\n");
+
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A1 NOOP\r\n"
+    ));
+    // The received data is processed before continuing with sending
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::DataReceived { .. })
+    ));
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::CommandSent { command, .. }) if command.tag == Tag::unvalidated("A1")
+    ));
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A2 NOOP\r\n"
+    ));
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::CommandSent { command, .. }) if command.tag == Tag::unvalidated("A2")
+    ));
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::NeedMoreInput))
+    ));
+}
+
+#[test]
+fn client_remembers_greeting_kind() {
+    let mut client = Client::new(client::Options::default());
+    assert_eq!(client.greeting_kind(), None);
+
+    client.enqueue_input(b"* PREAUTH ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+    assert_eq!(client.greeting_kind(), Some(GreetingKind::PreAuth));
+}
+
+#[test]
+fn client_tracks_capabilities_and_enabled_extensions() {
+    let mut client = Client::new(client::Options::default());
+    assert!(client.capabilities().is_none());
+
+    client.enqueue_input(b"* OK [CAPABILITY IMAP4REV1 ENABLE] ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+    assert_eq!(
+        client.capabilities().unwrap().as_ref(),
+        [Capability::Imap4Rev1, Capability::Enable]
+    );
+
+    client.enqueue_input(b"* CAPABILITY IMAP4REV1 ENABLE IDLE\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::DataReceived { .. })
+    ));
+    assert_eq!(
+        client.capabilities().unwrap().as_ref(),
+        [Capability::Imap4Rev1, Capability::Enable, Capability::Idle]
+    );
+
+    assert!(client.enabled().is_empty());
+    client.enqueue_input(b"* ENABLED UTF8=ACCEPT\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::DataReceived { .. })
+    ));
+    assert_eq!(client.enabled(), [CapabilityEnable::Utf8(Utf8Kind::Accept)]);
+}
+
+#[test]
+fn client_requires_tls_handshake_after_starttls() {
+    let mut client = Client::new(client::Options::default());
+
+    client.enqueue_input(b"* OK [CAPABILITY IMAP4REV1 STARTTLS LITERAL+] ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+    assert!(client.capabilities().is_some());
+
+    let handle = client.enqueue_command(Command::new("A1", CommandBody::StartTLS).unwrap());
+    client.enqueue_command(Command::new("A2", CommandBody::Noop).unwrap());
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A1 STARTTLS\r\n"
+    ));
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::CommandSent { .. })
+    ));
+    // NOOP is not sent before the TLS handshake
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::NeedMoreInput))
+    ));
+
+    // The injected response must not be interpreted after the TLS handshake
+    client.enqueue_input(b"A1 OK ...\r\n* 1 EXISTS\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::TlsHandshakeRequired { handle: received_handle, .. })
+            if received_handle == handle
+    ));
+
+    // The capabilities from before the TLS handshake were discarded, so LITERAL+ is not used
+    assert!(client.capabilities().is_none());
+    let (_, login) = CommandCodec::default()
+        .decode(b"A3 LOGIN {5}\r\nalice password\r\n")
+        .unwrap();
+    assert!(matches!(
+        client.encode_command(&login).as_slice(),
+        [
+            _,
+            CommandFragment::Literal {
+                mode: LiteralMode::Sync,
+                ..
+            },
+            _
+        ]
+    ));
+
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A2 NOOP\r\n"
+    ));
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::CommandSent { .. })
+    ));
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::NeedMoreInput))
+    ));
+}
+
+#[test]
+fn client_continues_sending_after_bye_during_starttls() {
+    let mut client = Client::new(client::Options::default());
+
+    client.enqueue_input(b"* OK ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+
+    let handle = client.enqueue_command(Command::new("A1", CommandBody::StartTLS).unwrap());
+    client.enqueue_command(Command::new("A2", CommandBody::Logout).unwrap());
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A1 STARTTLS\r\n"
+    ));
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::CommandSent { .. })
+    ));
+
+    // The server closes the connection without answering STARTTLS
+    client.enqueue_input(b"* BYE ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::StartTlsAborted { handle: received_handle, status: Status::Bye(_) })
+            if received_handle == handle
+    ));
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A2 LOGOUT\r\n"
+    ));
+}
+
+#[test]
+fn client_sends_bare_lf_line_endings() {
+    let options = client::Options {
+        line_ending: LineEnding::Lf,
+        ..Default::default()
+    };
+    let mut client = Client::new(options);
+
+    client.enqueue_input(b"* OK ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+
+    client.enqueue_command(Command::new("A1", CommandBody::Noop).unwrap());
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A1 NOOP\n"
+    ));
+}
+
+#[test]
+fn client_passes_raw_bytes_to_wire_tap() {
+    let tapped = Arc::new(Mutex::new(Vec::new()));
+    let mut client = Client::new(client::Options::default());
+    client.set_wire_tap({
+        let tapped = tapped.clone();
+        move |direction, bytes| tapped.lock().unwrap().push((direction, bytes.to_vec()))
+    });
+
+    client.enqueue_input(b"* OK ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+
+    let command = Command::new("A1", CommandBody::Noop).unwrap();
+    client.enqueue_command(command);
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A1 NOOP\r\n"
+    ));
+
+    assert_eq!(
+        *tapped.lock().unwrap(),
+        [
+            (WireDirection::Received, b"* OK ...\r\n".to_vec()),
+            (WireDirection::Sent, b"A1 NOOP\r\n".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn client_and_server_are_send_and_sync() {
+    fn assert_send_and_sync<T: Send + Sync>() {}
+
+    assert_send_and_sync::<Client>();
+    assert_send_and_sync::<Server>();
+}
+
+#[test]
+fn client_encodes_command_without_sending() {
+    let mut client = Client::new(client::Options::default());
+
+    let (_, command) = CommandCodec::default()
+        .decode(b"A1 LOGIN {5}\r\nalice password\r\n")
+        .unwrap();
+
+    assert_eq!(
+        client.encode_command(&command),
+        vec![
+            CommandFragment::Line {
+                data: b"A1 LOGIN {5}\r\n".to_vec()
+            },
+            CommandFragment::Literal {
+                data: b"alice".to_vec(),
+                mode: LiteralMode::Sync
+            },
+            CommandFragment::Line {
+                data: b" password\r\n".to_vec()
+            },
+        ]
+    );
+
+    // Nothing was enqueued
+    client.enqueue_input(b"* OK ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::NeedMoreInput))
+    ));
+}
+
+#[test]
+fn client_overrides_literal_strategy_per_command() {
+    let mut client = Client::new(client::Options::default());
+
+    client.enqueue_input(b"* OK [CAPABILITY IMAP4REV1 LITERAL+] ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+
+    let (_, command) = CommandCodec::default()
+        .decode(b"A1 LOGIN {5}\r\nalice password\r\n")
+        .unwrap();
+    client.enqueue_command_with_literal_strategy(
+        command.into_static(),
+        LiteralStrategy::NonSyncUpTo(4),
+    );
+
+    // The literal is too long for a non-synchronizing literal
+    assert!(matches!(
+        client.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A1 LOGIN {5}\r\n"
+    ));
+}
+
+#[test]
+fn server_answers_capability_command() {
+    let mut options = server::Options::default();
+    options.capabilities = Some(Vec1::from(Capability::Imap4Rev1));
+    let mut server = Server::new(options, Greeting::ok(None, "...").unwrap());
+
+    // The capabilities are announced in the greeting
+    assert!(matches!(
+        server.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"* OK [CAPABILITY IMAP4REV1] ...\r\n"
+    ));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::GreetingSent { .. })
+    ));
+
+    // The command is answered without emitting an event
+    server.enqueue_input(b"A1 CAPABILITY\r\n");
+    let mut output = Vec::new();
+    loop {
+        match server.next() {
+            Err(Interrupt::Io(Io::Output(bytes))) => output.extend(bytes),
+            Err(Interrupt::Io(Io::NeedMoreInput)) => break,
+            result => panic!("Server emitted unexpected result: {result:?}"),
+        }
+    }
+    assert_eq!(
+        output,
+        b"* CAPABILITY IMAP4REV1\r\nA1 OK CAPABILITY completed\r\n"
+    );
+}
+
+#[test]
+fn server_answers_id_command() {
+    let mut options = server::Options::default();
+    options.id_parameters = Some(vec![(
+        IString::try_from("name").unwrap(),
+        NString(Some(IString::try_from("imap-next").unwrap())),
+    )]);
+    let mut server = Server::new(options, Greeting::ok(None, "...").unwrap());
+
+    assert!(matches!(
+        server.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"* OK ...\r\n"
+    ));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::GreetingSent { .. })
+    ));
+
+    server.enqueue_input(b"A1 ID (\"name\" \"client\")\r\n");
+    let Ok(server::Event::IdReceived {
+        parameters: Some(parameters),
+    }) = server.next()
+    else {
+        panic!("expected ID");
+    };
+    assert_eq!(
+        parameters,
+        [(
+            IString::try_from("name").unwrap(),
+            NString(Some(IString::try_from("client").unwrap())),
+        )]
+    );
+
+    let mut output = Vec::new();
+    loop {
+        match server.next() {
+            Err(Interrupt::Io(Io::Output(bytes))) => output.extend(bytes),
+            Err(Interrupt::Io(Io::NeedMoreInput)) => break,
+            result => panic!("Server emitted unexpected result: {result:?}"),
+        }
+    }
+    assert_eq!(
+        output,
+        b"* ID (\"name\" \"imap-next\")\r\nA1 OK ID completed\r\n"
+    );
+}
+
+#[test]
+fn server_rejects_commands_exceeding_max_pending_commands() {
+    let mut options = server::Options::default();
+    options.max_pending_commands = Some(1);
+    let mut server = Server::new(options, Greeting::ok(None, "...").unwrap());
+
+    assert!(matches!(
+        server.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"* OK ...\r\n"
+    ));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::GreetingSent { .. })
+    ));
+
+    server.enqueue_input(b"A1 NOOP\r\nA2 NOOP\r\n");
+    let Ok(server::Event::CommandReceived { command }) = server.next() else {
+        panic!("expected command");
+    };
+    assert_eq!(command.tag, Tag::try_from("A1").unwrap());
+    let Ok(server::Event::CommandRateLimited { command }) = server.next() else {
+        panic!("expected rate limited command");
+    };
+    assert_eq!(command.tag, Tag::try_from("A2").unwrap());
+
+    let mut output = Vec::new();
+    loop {
+        match server.next() {
+            Err(Interrupt::Io(Io::Output(bytes))) => output.extend(bytes),
+            Err(Interrupt::Io(Io::NeedMoreInput)) => break,
+            result => panic!("Server emitted unexpected result: {result:?}"),
+        }
+    }
+    assert_eq!(output, b"A2 NO [LIMIT] Too many pending commands\r\n");
+
+    // Answering the pending command allows receiving the next one
+    let status = Status::ok(Some(Tag::try_from("A1").unwrap()), None, "...").unwrap();
+    let handle = server.enqueue_status(status);
+    assert!(matches!(
+        server.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A1 OK ...\r\n"
+    ));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::ResponseSent { handle: sent_handle, .. }) if sent_handle == handle
+    ));
+
+    server.enqueue_input(b"A3 NOOP\r\n");
+    let Ok(server::Event::CommandReceived { command }) = server.next() else {
+        panic!("expected command");
+    };
+    assert_eq!(command.tag, Tag::try_from("A3").unwrap());
+}
+
+#[test]
+fn server_discards_plaintext_after_starttls() {
+    let mut options = server::Options::default();
+    options.capabilities =
+        Some(Vec1::try_from(vec![Capability::Imap4Rev1, Capability::StartTls]).unwrap());
+    let mut server = Server::new(options, Greeting::ok(None, "...").unwrap());
+
+    assert!(matches!(
+        server.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"* OK [CAPABILITY IMAP4REV1 STARTTLS] ...\r\n"
+    ));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::GreetingSent { .. })
+    ));
+
+    // The pipelined NOOP must not be executed after the TLS handshake
+    server.enqueue_input(b"A1 STARTTLS\r\nA2 NOOP\r\n");
+    let Ok(server::Event::StartTlsCommandReceived { tag }) = server.next() else {
+        panic!("expected STARTTLS");
+    };
+
+    // Only a tagged OK for the STARTTLS command is accepted
+    assert!(server
+        .starttls_accept(Status::ok(None, None, "...").unwrap())
+        .is_err());
+    assert!(server
+        .starttls_accept(Status::ok(Some(Tag::unvalidated("A2")), None, "...").unwrap())
+        .is_err());
+    assert!(server
+        .starttls_accept(Status::no(Some(tag.clone()), None, "...").unwrap())
+        .is_err());
+
+    let handle = server
+        .starttls_accept(Status::ok(Some(tag), None, "...").unwrap())
+        .unwrap();
+    assert!(matches!(
+        server.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A1 OK ...\r\n"
+    ));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::TlsHandshakeRequired { handle: sent_handle }) if sent_handle == handle
+    ));
+
+    server.enqueue_input(b"A3 NOOP\r\n");
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::CommandReceived { command }) if command.tag == Tag::unvalidated("A3")
+    ));
+}
+
+#[test]
+fn server_forwards_starttls_if_not_announced() {
+    let mut server = Server::new(
+        server::Options::default(),
+        Greeting::ok(None, "...").unwrap(),
+    );
+
+    assert!(matches!(
+        server.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"* OK ...\r\n"
+    ));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::GreetingSent { .. })
+    ));
+
+    server.enqueue_input(b"A1 STARTTLS\r\n");
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::CommandReceived { command }) if command.body == CommandBody::StartTLS
+    ));
+}
+
+#[cfg(feature = "deflate")]
+#[test]
+fn client_compresses_after_start_compression() {
+    use crate::deflate::Deflate;
+
+    let mut server_deflate = Deflate::new();
+    let mut client = Client::new(client::Options::default());
+
+    client.enqueue_input(b"* OK ...\r\n");
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::GreetingReceived { .. })
+    ));
+
+    // The server might send compressed data right after the tagged status
+    let mut input = b"A1 OK DEFLATE active\r\n".to_vec();
+    input.extend(server_deflate.compress(b"* 1 EXISTS\r\n"));
+    client.enqueue_input(&input);
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::StatusReceived { .. })
+    ));
+
+    client.start_compression();
+    assert!(matches!(
+        client.next(),
+        Ok(client::Event::DataReceived { .. })
+    ));
+
+    client.enqueue_command(Command::new("A2", CommandBody::Noop).unwrap());
+    let Err(Interrupt::Io(Io::Output(bytes))) = client.next() else {
+        panic!("expected output");
+    };
+    server_deflate.enqueue_input(&bytes);
+    assert_eq!(server_deflate.decompress().unwrap(), b"A2 NOOP\r\n");
+}
+
+#[cfg(feature = "deflate")]
+#[test]
+fn server_compresses_after_start_compression() {
+    use crate::deflate::Deflate;
+
+    let mut client_deflate = Deflate::new();
+    let mut server = Server::new(
+        server::Options::default(),
+        Greeting::ok(None, "...").unwrap(),
+    );
+
+    assert!(matches!(
+        server.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"* OK ...\r\n"
+    ));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::GreetingSent { .. })
+    ));
+
+    // Accept the `COMPRESS DEFLATE` command
+    let status = Status::ok(Some(Tag::unvalidated("A1")), None, "DEFLATE active").unwrap();
+    server.enqueue_status(status);
+    assert!(matches!(
+        server.next(),
+        Err(Interrupt::Io(Io::Output(bytes))) if bytes == b"A1 OK DEFLATE active\r\n"
+    ));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::ResponseSent { .. })
+    ));
+
+    server.start_compression();
+    server.enqueue_input(&client_deflate.compress(b"A2 NOOP\r\n"));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::CommandReceived { .. })
+    ));
+
+    // The decompressed command is much larger than the compressed bytes
+    let message = b"Subject: Hello\r\n\r\n".repeat(1000);
+    let mut append = format!("A3 APPEND INBOX {{{}+}}\r\n", message.len()).into_bytes();
+    append.extend(&message);
+    append.extend(b"\r\n");
+    server.enqueue_input(&client_deflate.compress(&append));
+    assert!(matches!(
+        server.next(),
+        Ok(server::Event::CommandReceived { command }) if command.tag == Tag::unvalidated("A3")
+    ));
+
+    server.enqueue_status(Status::ok(Some(Tag::unvalidated("A2")), None, "...").unwrap());
+    let Err(Interrupt::Io(Io::Output(bytes))) = server.next() else {
+        panic!("expected output");
+    };
+    client_deflate.enqueue_input(&bytes);
+    assert_eq!(client_deflate.decompress().unwrap(), b"A2 OK ...\r\n");
+}